@@ -1,15 +1,318 @@
-mod db;
-mod zest;
-
-#[macro_use]
-extern crate clap;
-use db::Database;
-use log::error;
-use log::LevelFilter;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use env_logger::WriteStyle;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{debug, error, info, warn};
+use log::{LevelFilter, Record};
 use std::error::Error;
-use zest::Zest;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+use zest_db::{Database, DatabaseError, PutReport, SearchOptions, UpdateReport};
+use zest_db::{Zest, ZestMeta};
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn print_report(report: &PutReport) {
+    debug!("Committed with opstamp {}", report.opstamp);
+    info!("{} notes added, {} updated", report.added, report.updated);
+    for (fname, e) in &report.failed {
+        error!("{} is could not be successfully added: {}", fname, e);
+    }
+}
+
+/// Set when the user hits Ctrl-C during a scan
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    if let Some(flag) = INTERRUPTED.get() {
+        flag.store(true, Ordering::Relaxed);
+    }
+    // Hitting Ctrl-C again kills zest right away
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+/// Makes Ctrl-C stop the scans of `db` while keeping the notes found until then, instead of
+/// killing zest and losing them
+fn cancel_scan_on_interrupt(db: &mut Database) {
+    let flag = INTERRUPTED.get_or_init(|| Arc::new(AtomicBool::new(false)));
+    db.set_cancel_flag(flag.clone());
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+fn print_update_report(report: &UpdateReport) {
+    debug!("Committed with opstamp {}", report.opstamp);
+    info!(
+        "{} notes added, {} updated, {} removed",
+        report.added, report.updated, report.removed
+    );
+    if report.truncated {
+        info!("Some new notes were not added yet, run the command again to add them");
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line, with the structured fields of the events, like the action and
+    /// the path of the notes added to or removed from the database
+    Json,
+}
+
+/// Collects the key-value pairs of a log record into a JSON object
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.to_string(), value.to_string().into());
+        Ok(())
+    }
+}
+
+fn format_json(buf: &mut env_logger::fmt::Formatter, record: &Record) -> std::io::Result<()> {
+    let mut fields = serde_json::Map::new();
+    fields.insert("timestamp".into(), buf.timestamp().to_string().into());
+    fields.insert("level".into(), record.level().as_str().into());
+    fields.insert("target".into(), record.target().into());
+    fields.insert("message".into(), record.args().to_string().into());
+    if let Err(e) = record.key_values().visit(&mut JsonFields(&mut fields)) {
+        fields.insert("error".into(), e.to_string().into());
+    }
+    writeln!(buf, "{}", serde_json::Value::Object(fields))
+}
+
+/// Asks whether to rebuild an index created by another version of zest, when there is someone to
+/// answer
+fn confirm_reindex() -> std::io::Result<bool> {
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(false);
+    }
+
+    eprint!("Your zest index was created with an older version. Reindex it now? [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+const QUERY_HELP: &str = "\
+Queries are tantivy queries, made of terms looked up in the title, the content, the tags, the
+section headings and the links of the notes.
+
+Fields:
+    title:foo       notes with foo in their title
+    content:foo     notes with foo in their content
+    tag:foo         notes tagged foo
+    file:foo        notes with foo in their path or file name
+    code:foo        notes with foo in a code block or an inline code span
+    ref:foo         notes linking to a note with foo in its path
+    section:foo     notes with foo in a section heading
+    created:[2024-01-01T00:00:00Z TO 2025-01-01T00:00:00Z}
+                    notes created in 2024
+    lastmod:[2024-01-01T00:00:00Z TO 2025-01-01T00:00:00Z}
+                    notes last modified in 2024, see also 'zest search --recent'
+
+Phrases:
+    \"hello world\"   notes containing hello immediately followed by world
+
+Boolean operators:
+    foo bar         notes containing foo or bar
+    foo AND bar     notes containing both foo and bar
+    +foo -bar       notes containing foo but not bar
+    (foo OR bar) AND tag:baz
+
+All the notes:
+    *";
+
+#[derive(Parser)]
+#[command(name = "zest", author = "Thomas Vigouroux <tomvig38@gmail.com>")]
+#[command(arg_required_else_help = true)]
+struct Cli {
+    /// Verbosity level
+    #[arg(short, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Configuration file to use instead of the default one
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Directory to store the index in instead of the default one
+    #[arg(long)]
+    index_dir: Option<PathBuf>,
+
+    /// Log how long each step of the database operations takes
+    #[arg(long)]
+    profile: bool,
+
+    /// Format of the logs
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Add documents to the database
+    Add {
+        /// Reindex the files even if they are already up to date
+        #[arg(short, long)]
+        force: bool,
+
+        /// Don't add anything if some of the files can't be parsed
+        #[arg(long)]
+        strict: bool,
+
+        /// Files to add in the database, directories are added recursively
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Search into the database for files and print their files and titles
+    Search {
+        /// Only print file paths
+        #[arg(short = 'f', long)]
+        only_files: bool,
+
+        /// Only print the number of matching notes
+        #[arg(long, conflicts_with = "only_files")]
+        count: bool,
+
+        /// Only search the notes modified in the last N days
+        #[arg(long, value_name = "N", conflicts_with = "count")]
+        recent: Option<u32>,
+
+        /// Tantivy query to run
+        #[arg(required = true)]
+        query_terms: Vec<String>, // We will actually concatenate those
+    },
+    /// Describes the query syntax, see 'zest help query'
+    #[command(after_help = QUERY_HELP)]
+    Query,
+    /// Prints the sections of a note, as 'start_line:end_line:level:heading'
+    Sections {
+        /// The note to print the sections of
+        file: String,
+    },
+    /// Checks a note for likely mistakes, like broken links or duplicate tags
+    Check {
+        /// The note to check
+        file: String,
+    },
+    /// Suggests completions for a partially typed query term, like 'tag:fo'
+    Suggest {
+        /// Beginning of the term to complete
+        partial: String,
+    },
+    /// Lists the tags of the notes, with the number of notes having each of them
+    Tags {
+        /// Only list the tags starting with this
+        #[arg(default_value = "")]
+        prefix: String,
+    },
+    /// Remove files matching the search term
+    Remove {
+        /// Tantivy query to run
+        #[arg(required = true)]
+        query_terms: Vec<String>,
+    },
+    /// Synchronizes the database, this is an alias to 'zest scan && zest refresh'
+    Update {
+        /// Add at most this number of new files, the next runs add the others
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Looks for untracked files in the configured paths and adds them to the database
+    #[command(alias = "new")]
+    Scan {
+        /// Add at most this number of new files, the next runs add the others
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Updates the changed files and removes the deleted ones from the database
+    Refresh,
+    /// Creates a new file, add it to the database, and returns it's path
+    Create {
+        /// Title of the note, "Untitled" by default
+        #[arg(short, long)]
+        title: Option<String>,
+        /// Tag to add to the note, can be repeated
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Reindexes the whole database as once. If some links are broken, this could fix it
+    Reindex,
+    /// Exports the notes as an Obsidian vault, converting their links to wikilinks
+    Export {
+        /// Directory to write the vault to
+        output_dir: PathBuf,
+    },
+    /// Prints statistics about the notes
+    Stats {
+        /// Lists the notes by number of links per 100 words, the least linked first
+        #[arg(long)]
+        by_link_density: bool,
+    },
+    /// Initializes zest, using your configuration. This is an alias to 'zest update && zest reindex'
+    Init,
+    /// Shows a graph representing the database
+    #[cfg(feature = "graph")]
+    Graph {
+        /// Only show the note at PATH and its neighbors
+        #[arg(long, value_name = "PATH")]
+        subgraph: Option<String>,
+        /// Show the neighbors of the subgraph up to N links away, the farthest ones have a dashed
+        /// border
+        #[arg(long, value_name = "N", default_value_t = 1, requires = "subgraph")]
+        depth: usize,
+    },
+}
+
+/// Prints `e`, along with what the user can do about it when it is known
+fn report_error(e: &(dyn Error + 'static), config: Option<&Path>) {
+    eprintln!("Error: {}", e);
+    let hint = match e.downcast_ref::<DatabaseError>() {
+        Some(DatabaseError::LockError(_)) => Some(String::from(
+            "Another zest instance is running, try again once it is done",
+        )),
+        Some(DatabaseError::ConfigError(_)) => {
+            Database::config_file(config).map(|p| format!("Check your config at {}", p.display()))
+        }
+        Some(DatabaseError::CorruptionError(_)) => Some(String::from(
+            "Run 'zest reindex' to rebuild the index from your notes",
+        )),
+        _ => None,
+    };
+    if let Some(hint) = hint {
+        eprintln!("{}", hint);
+    }
+}
+
+fn main() {
+    // Rust ignores SIGPIPE, which makes println! panic when the output is piped to a command that
+    // exits early, like head. Be killed silently instead, as other command line tools.
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+
+    let cli = Cli::parse();
+    let config = cli.config.clone();
+    if let Err(e) = run(cli) {
+        report_error(&*e, config.as_deref());
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
     // let mut schema_builder = Schema::builder();
     // let title = schema_builder.add_text_field("title", TEXT);
     // let content = schema_builder.add_text_field("content", TEXT);
@@ -23,136 +326,274 @@ fn main() -> Result<(), Box<dyn Error>> {
     //     );
 
     // println!("{:?}", doc);
-    let mut app = clap_app!(zest =>
-      (author: "Thomas Vigouroux <tomvig38@gmail.com>")
-      (@arg verbose: -v ... "Verbosity level")
-      (@subcommand add =>
-       (about: "Add documents to the database")
-       (@arg FILE: +required ... "Files to add in the database")
-      )
-      (@subcommand search =>
-       (about: "Search into the database for files and print their files and titles")
-       (@arg only_files: -f --only-files "Only print file paths")
-       (@arg QUERY_TERMS: +required ... "Tantivy query to run") // We will actually concatenate those
-      )
-      (@subcommand remove =>
-       (about: "Remove files matching the search term")
-       (@arg QUERY_TERMS: +required ... "Tantivy query to run")
-      )
-      (@subcommand update =>
-       (about: "Synchronizes the database, also checks for new files")
-      )
-      (@subcommand new =>
-       (about: "Checks for new files in the database")
-       )
-      (@subcommand create =>
-       (about: "Creates a new file, add it to the database, and returns it's path")
-       )
-      (@subcommand reindex =>
-       (about: "Reindexes the whole database as once. If some links are broken, this could fix it")
-       )
-      (@subcommand init =>
-       (about: "Initializes zest, using your configuration. This is an alias to 'zest update && zest reindex'")
-       )
-    )
-    .setting(clap::AppSettings::ArgRequiredElseHelp);
 
-    #[cfg(feature = "graph")]
-    {
-        app = app.subcommand(
-            clap::SubCommand::with_name("graph").about("Shows a graph representing the database"),
-        );
-    }
-
-    let matches = app.get_matches();
+    // env_logger already disables colors when stderr is not a terminal, but not when NO_COLOR is
+    // set (see https://no-color.org)
+    let write_style = if std::env::var_os("NO_COLOR").is_some() {
+        WriteStyle::Never
+    } else {
+        WriteStyle::Auto
+    };
 
-    env_logger::builder()
-        .filter_level(match matches.occurrences_of("verbose") {
+    let mut logger = env_logger::builder();
+    logger
+        .write_style(write_style)
+        .filter_level(match cli.verbose {
             0 => LevelFilter::Error,
             1 => LevelFilter::Warn,
             2 => LevelFilter::Info,
             3 => LevelFilter::Debug,
             _ => LevelFilter::Trace,
-        })
-        .init();
-
-    let mut db = Database::open()?;
-
-    if matches.subcommand_matches("update").is_some() {
-        db.update()?;
-        return Ok(());
+        });
+    if cli.profile && cli.verbose < 3 {
+        // The timing information is logged at the debug level, show it without the rest
+        logger.filter_module(zest_db::PROFILE_TARGET, LevelFilter::Debug);
     }
-
-    if matches.subcommand_matches("new").is_some() {
-        db.new()?;
-        return Ok(());
+    if let LogFormat::Json = cli.log_format {
+        logger.format(format_json);
     }
+    logger.init();
 
-    if let Some(matches) = matches.subcommand_matches("search") {
-        let terms: Vec<&str> = matches.values_of("QUERY_TERMS").unwrap().collect();
-        let query = terms.join(" ");
-
-        if matches.is_present("only_files") {
-            for f in db.list(query)? {
-                println!("{}", f);
+    match cli.command {
+        Command::Query => {
+            println!("{}", QUERY_HELP);
+            return Ok(());
+        }
+        Command::Sections { file } => {
+            let z = Zest::from_file(file)?;
+            for s in z.sections {
+                println!("{}:{}:{}:{}", s.start_line, s.end_line, s.level, s.heading);
             }
-        } else {
-            for r in db.search(query)? {
-                println!("{}: {}", r.file, r.title);
+            return Ok(());
+        }
+        Command::Check { file } => {
+            for warning in Zest::from_file(file)?.validate() {
+                println!("{}", warning);
             }
+            return Ok(());
         }
-        return Ok(());
+        _ => {}
     }
 
-    if matches.subcommand_matches("create").is_some() {
-        let (path, _) = db.create()?;
-        println!("{}", path);
-        return Ok(());
-    }
+    // Searching and rendering the graph never modify the index, so avoid taking the writer lock
+    let readonly = match cli.command {
+        Command::Search { .. }
+        | Command::Suggest { .. }
+        | Command::Tags { .. }
+        | Command::Stats { .. }
+        | Command::Export { .. } => true,
+        #[cfg(feature = "graph")]
+        Command::Graph { .. } => true,
+        _ => false,
+    };
+    let index_dir = cli.index_dir.as_deref();
+    let config = cli.config.as_deref();
+    let opened = match (index_dir, config) {
+        (None, None) if readonly => Database::open_readonly(),
+        (None, None) => Database::open(),
+        (index_dir, config) => Database::open_or_create_at(index_dir, config, readonly),
+    };
+    let reindexing = matches!(cli.command, Command::Reindex);
+    let rebuild = match opened {
+        Err(DatabaseError::SchemaMismatch) => reindexing || confirm_reindex()?,
+        // Nothing can be read from a corrupted index, only rebuild it when asked to
+        Err(DatabaseError::CorruptIndex(_)) => reindexing,
+        Err(DatabaseError::VersionMismatch { .. }) => reindexing,
+        _ => false,
+    };
+    let mut db = if rebuild {
+        let mut db = Database::recreate_at(index_dir, config)?;
+        db.update()?;
+        print_report(&db.reindex()?);
+        if reindexing {
+            return Ok(());
+        }
+        db
+    } else {
+        opened?
+    };
+    debug!("Index opened at opstamp {}", db.current_opstamp()?);
 
-    if matches.subcommand_matches("reindex").is_some() {
-        db.reindex()?;
-        return Ok(());
-    }
+    match cli.command {
+        Command::Query | Command::Sections { .. } | Command::Check { .. } => {
+            unreachable!("handled without the database")
+        }
+        Command::Update { limit } => {
+            cancel_scan_on_interrupt(&mut db);
+            print_update_report(&db.update_with_limit(limit)?);
+        }
+        Command::Scan { limit } => {
+            cancel_scan_on_interrupt(&mut db);
+            print_update_report(&db.scan_with_limit(limit)?);
+        }
+        Command::Refresh => {
+            print_update_report(&db.refresh()?);
+        }
+        Command::Search {
+            only_files,
+            count,
+            recent,
+            query_terms,
+        } => {
+            let query = query_terms.join(" ");
 
-    if matches.subcommand_matches("init").is_some() {
-        db.update()?;
-        db.reindex()?;
-        return Ok(());
-    }
+            if count {
+                println!("{}", db.count_results(query)?);
+                return Ok(());
+            }
 
-    if let Some(matches) = matches.subcommand_matches("remove") {
-        let terms: Vec<&str> = matches.values_of("QUERY_TERMS").unwrap().collect();
-        let query = terms.join(" ");
-        db.remove(query)?;
-        return Ok(());
-    }
+            const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+            let mut opts = SearchOptions::default().only_paths(only_files);
+            // Going back before 1970 is the same as having no bound, and may not even be a valid
+            // date for the index
+            let since = recent
+                .and_then(|days| DAY.checked_mul(days))
+                .and_then(|age| SystemTime::now().checked_sub(age))
+                .filter(|since| *since > SystemTime::UNIX_EPOCH);
+            if let Some(since) = since {
+                opts = opts.since(since.into());
+            }
+            let found = db.reader().query(&query, opts)?;
+            if found.hits.is_empty() {
+                eprintln!("No results for query: {}", query);
+            }
+            for hit in found.hits {
+                match hit.note {
+                    None => println!("{}", hit.path),
+                    Some(r) => match r.description() {
+                        Some(description) => println!("{}: {} - {}", r.file, r.title, description),
+                        None => println!("{}: {}", r.file, r.title),
+                    },
+                }
+            }
+        }
+        Command::Suggest { partial } => {
+            for s in db.suggest_query(&partial)? {
+                println!("{}", s);
+            }
+        }
+        Command::Tags { prefix } => {
+            for (tag, count) in db.tags_starting_with(&prefix)? {
+                println!("{}\t{}", tag, count);
+            }
+        }
+        Command::Create { title, tags } => {
+            let meta = ZestMeta {
+                tags,
+                ..ZestMeta::default()
+            };
+            let (path, _, _) = db.create(title.as_deref(), &meta)?;
+            println!("{}", path);
+        }
+        Command::Reindex => {
+            print_report(&db.reindex()?);
+        }
+        Command::Init => {
+            print_update_report(&db.update()?);
+            print_report(&db.reindex()?);
+        }
+        Command::Export { output_dir } => {
+            let count = db.export_obsidian(&output_dir)?;
+            println!("Exported {} notes", count);
+        }
+        Command::Stats { by_link_density } => {
+            let mut densities: Vec<(String, f64)> = db.link_density_stats()?.into_iter().collect();
+            if by_link_density {
+                densities.sort_by(|(a, a_density), (b, b_density)| {
+                    a_density.total_cmp(b_density).then(a.cmp(b))
+                });
+                for (path, density) in densities {
+                    println!("{:.2}\t{}", density, path);
+                }
+            } else {
+                let count = densities.len();
+                let mean = densities.iter().map(|(_, d)| d).sum::<f64>() / count.max(1) as f64;
+                let isolated = densities.iter().filter(|(_, d)| *d == 0.0).count();
+                println!("{} notes", count);
+                println!("{} notes without links", isolated);
+                println!("{:.2} links per 100 words on average", mean);
+            }
+        }
+        Command::Remove { query_terms } => {
+            let query = query_terms.join(" ");
+            db.remove(query)?;
+        }
+        Command::Add {
+            force,
+            strict,
+            files,
+        } => {
+            let up_to_date = |fname: &str| {
+                let skip = !force && db.is_up_to_date(fname).unwrap_or(false);
+                if skip {
+                    warn!("{} is already up to date, skipping.", fname);
+                }
+                skip
+            };
 
-    if let Some(matches) = matches.subcommand_matches("add") {
-        let to_add: Vec<Zest> = matches
-            .values_of("FILE")
-            .unwrap()
-            .filter_map(|fname| match Zest::from_file(fname.to_owned()) {
-                Ok(z) => Some(z),
-                Err(e) => {
-                    error!("{} is could not be successfully added: {}", fname, e);
-                    None
+            let mut to_add: Vec<Zest> = Vec::new();
+            let mut failed = 0;
+            for fname in &files {
+                if Path::new(fname).is_dir() {
+                    let notes = Zest::from_directory(
+                        Path::new(fname),
+                        db.max_file_size(),
+                        db.follow_symlinks(),
+                    );
+                    for z in notes {
+                        match z {
+                            Ok(z) if up_to_date(&z.file) => {}
+                            Ok(z) => to_add.push(z),
+                            Err(e) => {
+                                error!(
+                                    "A note of {} could not be successfully added: {}",
+                                    fname, e
+                                );
+                                failed += 1;
+                            }
+                        }
+                    }
+                    continue;
                 }
-            })
-            .collect();
-        db.put_multiple(to_add)?;
-        return Ok(());
-    }
 
-    #[cfg(feature = "graph")]
-    if matches.subcommand_matches("graph").is_some() {
-        let mut tmp_dir = std::env::temp_dir();
-        tmp_dir.push("graph.dot");
-        let path = tmp_dir.to_str().unwrap();
-        println!("{}", path);
-        let mut file = std::fs::File::create(tmp_dir)?;
-        dot::render(&db, &mut file).unwrap();
-        return Ok(());
+                if up_to_date(fname) {
+                    continue;
+                }
+                match Zest::from_file_with_limit(fname.to_owned(), db.max_file_size()) {
+                    Ok(z) => to_add.push(z),
+                    Err(e) => {
+                        error!("{} is could not be successfully added: {}", fname, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if strict && failed > 0 {
+                return Err(
+                    format!("{} files could not be parsed, nothing was added", failed).into(),
+                );
+            }
+
+            let report = db.put_multiple(to_add)?;
+            print_report(&report);
+            failed += report.failed.len();
+            if failed > 0 {
+                return Err(format!("{} files could not be added", failed).into());
+            }
+        }
+        #[cfg(feature = "graph")]
+        Command::Graph { subgraph, depth } => {
+            let mut tmp_dir = std::env::temp_dir();
+            tmp_dir.push("graph.dot");
+            let path = tmp_dir.to_str().unwrap();
+            println!("{}", path);
+            let mut file = std::fs::File::create(tmp_dir)?;
+            if let Some(center) = subgraph {
+                let subgraph = db.subgraph(Zest::from_file(center)?, depth)?;
+                dot::render(&subgraph, &mut file).unwrap();
+            } else {
+                dot::render(&db, &mut file).unwrap();
+            }
+        }
     }
 
     Ok(())
@@ -0,0 +1,3266 @@
+//! The notes database behind the `zest` command: parse notes with [`Zest`], and index and search
+//! them with [`Database`].
+
+pub mod zest;
+
+pub use crate::zest::{FileFormat, Section, Zest, ZestMeta, ZestParsingError};
+
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Write as _};
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufReader, ErrorKind, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysinfo::System;
+use tantivy::collector::{Count, DocSetCollector, TopDocs};
+use tantivy::directory::error::OpenReadError;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{
+    AllQuery, BooleanQuery, Query, QueryParser, QueryParserError, RangeQuery, TermQuery,
+};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, Term, Type, INDEXED, STORED, STRING, TEXT,
+};
+use tantivy::{DateTime, LeasedItem, Searcher};
+use tantivy::{DocAddress, Document, UserOperation};
+use tantivy::{Index, IndexReader, IndexWriter, Opstamp, TantivyError};
+use xdg::BaseDirectories;
+
+#[cfg(feature = "graph")]
+use dot::{GraphWalk, Labeller};
+#[cfg(feature = "graph")]
+use std::borrow::Cow;
+
+const TITLE_FIELD: &str = "title";
+const CONTENT_FIELD: &str = "content";
+const TAG_FIELD: &str = "tag";
+const FILE_FIELD: &str = "file";
+const PATH_FIELD: &str = "path";
+const REF_FIELD: &str = "ref";
+const LAST_MODIF_FIELD: &str = "lastmod";
+const CREATED_FIELD: &str = "created";
+const HASH_FIELD: &str = "hash";
+const CODE_FIELD: &str = "code";
+const LINK_FIELD: &str = "link";
+const SECTION_FIELD: &str = "section";
+/// Fields used by zest itself, which are not meant to be queried by the user
+const INTERNAL_FIELDS: [&str; 3] = [PATH_FIELD, HASH_FIELD, LINK_FIELD];
+
+/// Log target of the timing information of the database operations, see `zest --profile`
+pub const PROFILE_TARGET: &str = "zest::profile";
+
+lazy_static! {
+    static ref XDG_DIR: BaseDirectories =
+        BaseDirectories::with_prefix("zest").expect("Impossible to create XDG directories");
+}
+
+/// The index can be rebuilt from the notes at any time, so it lives in `$XDG_CACHE_HOME/zest/index`
+fn default_index_dir(xdg: &BaseDirectories) -> std::io::Result<PathBuf> {
+    xdg.create_cache_directory("index")
+}
+
+/// The configuration lives in `$XDG_CONFIG_HOME/zest/config.yml`
+fn default_config_file(xdg: &BaseDirectories) -> std::io::Result<PathBuf> {
+    xdg.place_config_file("config.yml")
+}
+
+/// Distinguishes the index directory not being writable from other errors, as it is something
+/// the user can act upon
+fn index_dir_error(e: std::io::Error) -> DatabaseError {
+    match e.kind() {
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+            DatabaseError::PermissionError(e)
+        }
+        _ => DatabaseError::DirectoryError(e),
+    }
+}
+
+/// Tells apart the errors of an index that can't be used anymore from the others when opening it
+fn open_index_error(e: TantivyError) -> DatabaseError {
+    match e {
+        TantivyError::SchemaError(_) => DatabaseError::SchemaMismatch,
+        TantivyError::DataCorruption(_)
+        | TantivyError::OpenReadError(OpenReadError::FileDoesNotExist(_)) => {
+            DatabaseError::CorruptIndex(e)
+        }
+        TantivyError::LockFailure(..) => DatabaseError::LockError(e),
+        e => DatabaseError::CreateError(e),
+    }
+}
+
+/// Number of times the reader is reloaded after a commit before giving up
+const RELOAD_ATTEMPTS: u32 = 3;
+/// Delay between two attempts to reload the reader
+const RELOAD_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Memory budget of the index writer
+const WRITER_BUDGET: usize = 50_000_000;
+/// Smallest budget tantivy accepts for a writer
+const WRITER_BUDGET_MIN: usize = 3_000_000;
+
+/// Returns the memory budget of the index writer: `WRITER_BUDGET`, or half of the available memory
+/// when it is lower, so that the writer does not exhaust the memory of small machines
+fn writer_budget() -> usize {
+    let mut system = System::new();
+    system.refresh_memory();
+    // Zero when the available memory can't be known on this platform
+    let available = system.available_memory() as usize;
+    if available == 0 || available / 2 >= WRITER_BUDGET {
+        return WRITER_BUDGET;
+    }
+
+    let budget = (available / 2).max(WRITER_BUDGET_MIN);
+    log::info!(
+        "Only {} bytes of memory available, limiting the index writer to {} bytes",
+        available,
+        budget
+    );
+    budget
+}
+
+fn default_max_results() -> usize {
+    1000
+}
+
+#[derive(Deserialize, Debug)]
+struct Config {
+    /// Directories of the notes, where `~` and environment variables like `$HOME` are expanded
+    #[serde(default)]
+    paths: Vec<String>,
+    /// Maximum number of notes returned by a search
+    #[serde(default = "default_max_results")]
+    max_results: usize,
+    /// Notes bigger than this are not indexed
+    #[serde(default)]
+    max_file_size_bytes: Option<u64>,
+    /// Whether to look for notes behind symbolic links
+    #[serde(default)]
+    follow_symlinks: bool,
+    /// Whether the `#tags` written in the text of the notes are indexed as tags
+    #[serde(default)]
+    inline_tags: bool,
+    /// Name of the notes made by `zest create`, see `note_file_name`
+    #[serde(default)]
+    filename_template: Option<String>,
+}
+
+impl Config {
+    /// Expands `~` and the environment variables in the configured paths, as a shell would
+    fn expand_paths(&mut self) -> Result<(), DatabaseError> {
+        for path in &mut self.paths {
+            let expanded = shellexpand::full(path)
+                .map_err(|e| DatabaseError::ConfigError(format!("{}: {}", path, e)))?;
+            *path = expanded.into_owned();
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            paths: Vec::new(),
+            max_results: default_max_results(),
+            max_file_size_bytes: None,
+            follow_symlinks: false,
+            inline_tags: false,
+            filename_template: None,
+        }
+    }
+}
+
+struct DatabaseSchema {
+    schema: Schema,
+    title: Field,
+    content: Field,
+    tag: Field,
+    file: Field,
+    path: Field,
+    reff: Field,
+    last_modif: Field,
+    created_at: Field,
+    hash: Field,
+    code: Field,
+    /// Exact paths of the notes linked to, so that the notes linking to a given one are found with
+    /// a single term lookup
+    link: Field,
+    /// Headings of the sections of the note
+    section: Field,
+}
+
+impl DatabaseSchema {
+    fn new() -> Self {
+        let mut schema_builder = Schema::builder();
+        let title = schema_builder.add_text_field(TITLE_FIELD, TEXT | STORED);
+        // The content is not stored, it would double the size of the index: the notes are always
+        // read again from disk, which `update` only does for the files that changed
+        let content = schema_builder.add_text_field(CONTENT_FIELD, TEXT);
+        let tag = schema_builder.add_text_field(TAG_FIELD, STRING);
+        let file = schema_builder.add_text_field(FILE_FIELD, TEXT);
+        let path = schema_builder.add_text_field(PATH_FIELD, STRING | STORED);
+        let reff = schema_builder.add_text_field(REF_FIELD, TEXT);
+        let last_modif = schema_builder.add_date_field(LAST_MODIF_FIELD, INDEXED | STORED);
+        let created_at = schema_builder.add_date_field(CREATED_FIELD, INDEXED | STORED);
+        let hash = schema_builder.add_text_field(HASH_FIELD, STRING | STORED);
+        let code = schema_builder.add_text_field(CODE_FIELD, TEXT);
+        let link = schema_builder.add_text_field(LINK_FIELD, STRING);
+        let section = schema_builder.add_text_field(SECTION_FIELD, TEXT);
+
+        let schema = schema_builder.build();
+
+        Self {
+            schema,
+            title,
+            content,
+            tag,
+            file,
+            path,
+            reff,
+            last_modif,
+            created_at,
+            hash,
+            code,
+            link,
+            section,
+        }
+    }
+}
+
+/// The `major.minor` version of tantivy in `version`, as given by `tantivy::version_string`. The
+/// format of the index only changes with it.
+fn tantivy_minor_version(version: &str) -> &str {
+    version
+        .strip_prefix("tantivy v")
+        .and_then(|v| v.split(',').next())
+        .and_then(|v| v.rsplit_once('.'))
+        .map_or(version, |(minor, _)| minor)
+}
+
+/// Checks that the index was written by the current version of tantivy, as recorded in the payload
+/// of its last commit. Indexes committed before it was recorded are assumed to be compatible.
+fn check_index_version(index: &Index) -> Result<(), DatabaseError> {
+    let created_with = match index.load_metas().map_err(open_index_error)?.payload {
+        Some(payload) => payload,
+        None => return Ok(()),
+    };
+    let index_version = tantivy_minor_version(&created_with);
+    let current = tantivy_minor_version(tantivy::version_string());
+    if index_version != current {
+        return Err(DatabaseError::VersionMismatch {
+            index: index_version.to_owned(),
+            current: current.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Name of the notes made by `Database::create`, unless configured otherwise
+const DEFAULT_FILENAME_TEMPLATE: &str = "%Y_%m_%d_%H_%M_%S.md";
+
+/// The name of a note created at `time`: `template` is a strftime format, where `{random}` is
+/// replaced by a random identifier. The `.md` extension is added unless the name already has a
+/// markdown one: the notes are written in markdown, and other files would not be scanned.
+fn note_file_name(template: &str, time: &DateTime) -> Result<String, DatabaseError> {
+    let random = format!("{:016x}", RandomState::new().build_hasher().finish());
+    let template = template.replace("{random}", &random);
+
+    let mut name = String::new();
+    write!(name, "{}", time.format(&template)).map_err(|_| {
+        DatabaseError::ConfigError(format!("invalid filename_template: {}", template))
+    })?;
+    if FileFormat::from_extension(Path::new(&name)) != Some(FileFormat::Markdown) {
+        name.push_str(".md");
+    }
+    Ok(name)
+}
+
+/// Whether a file modified at `current` changed since it was indexed with the modification time
+/// `stored`. The index only keeps whole seconds, so the comparison is done at the second precision:
+/// comparing finer timestamps would consider every file with a sub-second modification time newer.
+fn is_newer(stored: &DateTime, current: &DateTime) -> bool {
+    current.timestamp() > stored.timestamp()
+}
+
+/// Calls `f` with each term of `field` starting with `prefix` and its document frequency, in each
+/// segment of the index. The term dictionary is sorted, so this only visits the matching terms.
+fn terms_with_prefix<F>(
+    searcher: &Searcher,
+    field: Field,
+    prefix: &str,
+    mut f: F,
+) -> Result<(), DatabaseError>
+where
+    F: FnMut(&str, u32),
+{
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader
+            .inverted_index(field)
+            .map_err(|e| DatabaseError::ReadError(e))?;
+        let mut terms = inverted_index
+            .terms()
+            .range()
+            .ge(prefix.as_bytes())
+            .into_stream()
+            .map_err(|e| DatabaseError::ReadError(e.into()))?;
+
+        while let Some((term, info)) = terms.next() {
+            if !term.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            f(&String::from_utf8_lossy(term), info.doc_freq);
+        }
+    }
+    Ok(())
+}
+
+/// What happened to a tracked file since it was indexed
+enum FileStatus {
+    Unchanged,
+    /// The file changed for the given reason, and was parsed again
+    Changed(&'static str, Box<Result<Zest, ZestParsingError>>),
+    /// The file is gone for the given reason
+    Deleted(&'static str),
+}
+
+/// Checks whether the file at `fname`, indexed with the modification time `changetime` and the
+/// content hash `hash`, changed since then, parsing it again if it did
+fn file_status(
+    fname: &str,
+    changetime: &DateTime,
+    hash: Option<&str>,
+    max_size: Option<u64>,
+) -> FileStatus {
+    let meta = match std::fs::metadata(fname) {
+        Ok(meta) if meta.is_dir() => {
+            log::info!("{} has been replaced by a directory", fname);
+            return FileStatus::Deleted("replaced by a directory");
+        }
+        Ok(meta) => meta,
+        // Could not retrieve it, it must have been deleted
+        Err(_) => return FileStatus::Deleted("deleted"),
+    };
+    // Without its modification time, the file is considered newer if it can't be hashed either
+    let curr_changetime = match meta.modified() {
+        Ok(time) => DateTime::from(time),
+        Err(e) => {
+            log::debug!("Could not retrieve {} last modified date: {}", fname, e);
+            DateTime::from(std::time::SystemTime::now())
+        }
+    };
+    let newer = is_newer(changetime, &curr_changetime);
+
+    // The timestamp alone is not reliable: it can stay the same when the file is copied or when
+    // the filesystem has a poor resolution, and it can change without the content changing (e.g.
+    // `touch`). The content hash has the final word.
+    let reason = match (hash, hash_file(fname)) {
+        (Some(hash), Ok(curr_hash)) if hash != curr_hash => {
+            log::debug!("{} has changed: {} != {}", fname, curr_hash, hash);
+            Some("content changed")
+        }
+        (Some(_), Ok(_)) => {
+            if newer {
+                // The stored timestamp is kept, so the file will be hashed again on the next
+                // update, which is still cheaper than reindexing it
+                log::debug!("{} has been touched but its content is the same", fname);
+            }
+            None
+        }
+        (None, _) => {
+            log::debug!("{} has no hash", fname);
+            Some("not hashed")
+        }
+        (_, Err(e)) => {
+            log::warn!("Could not compute {} hash: {}", fname, e);
+            if newer {
+                log::debug!(
+                    "{} has changed: {} > {}",
+                    fname,
+                    curr_changetime,
+                    changetime
+                );
+            }
+            newer.then_some("newer timestamp")
+        }
+    };
+
+    match reason {
+        Some(reason) => FileStatus::Changed(
+            reason,
+            Box::new(Zest::from_file_with_limit(fname.to_owned(), max_size)),
+        ),
+        None => FileStatus::Unchanged,
+    }
+}
+
+/// Parses the indexed note at `fname` to return it from a search. Notes that can't be read anymore
+/// are skipped until the index is refreshed.
+fn read_indexed_note(fname: String) -> Option<Zest> {
+    match Zest::from_file(fname.clone()) {
+        Ok(z) => Some(z),
+        Err(ZestParsingError::SourceError(e)) if e.kind() == ErrorKind::IsADirectory => {
+            log::warn!(
+                "{} has been replaced by a directory, run 'zest refresh' to remove it from the index",
+                fname
+            );
+            None
+        }
+        Err(e) => {
+            log::debug!("Skipping {}: {}", fname, e);
+            None
+        }
+    }
+}
+
+/// Computes the SHA256 of the content of `path`, as an hexadecimal string
+fn hash_file(path: &str) -> std::io::Result<String> {
+    let content = std::fs::read(path)?;
+    Ok(format!("{:x}", Sha256::digest(&content)))
+}
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    ConfigError(String),
+    DirectoryError(std::io::Error),
+    PermissionError(std::io::Error),
+    OpenError(tantivy::directory::error::OpenDirectoryError),
+    CreateError(tantivy::TantivyError),
+    PutError(tantivy::TantivyError),
+    ReadError(tantivy::TantivyError),
+    QueryError(String, QueryParserError),
+    ParseError(String, ZestParsingError),
+    CorruptionError(&'static str),
+    /// The index was created by another version of zest, with another schema
+    SchemaMismatch,
+    /// Files of the index are missing or can't be read
+    CorruptIndex(tantivy::TantivyError),
+    /// The index was written by another version of tantivy, whose format may differ
+    VersionMismatch {
+        index: String,
+        current: String,
+    },
+    ReadOnly,
+    /// Another process holds the writer lock of the index
+    LockError(tantivy::TantivyError),
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DirectoryError(e) => e.fmt(f),
+            Self::PermissionError(e) => write!(
+                f,
+                "Could not write to the index directory ({}), check the filesystem permissions or \
+                 use --index-dir to store the index elsewhere",
+                e
+            ),
+            Self::OpenError(e) => e.fmt(f),
+            Self::CreateError(e) | Self::PutError(e) | Self::ReadError(e) => e.fmt(f),
+            Self::QueryError(q, e) => write!(f, "{}", query_error_hint(q, e)),
+            Self::ConfigError(e) => write!(f, "Configuration error: {}", e),
+            Self::ParseError(fname, e) => write!(f, "Could not parse {}: {}", fname, e),
+            Self::CorruptionError(e) => write!(f, "Corruption detected: {}", e),
+            Self::SchemaMismatch => write!(
+                f,
+                "Your zest index was created with an older version. Run 'zest reindex' to upgrade."
+            ),
+            Self::CorruptIndex(e) => write!(
+                f,
+                "Index is corrupted ({}). Run 'zest reindex' to rebuild it from source files.",
+                e
+            ),
+            Self::VersionMismatch { index, current } => write!(
+                f,
+                "Index was created with Tantivy {}, current is {}. Run 'zest reindex'.",
+                index, current
+            ),
+            Self::ReadOnly => write!(f, "Database is opened in read-only mode"),
+            // The error of tantivy is quite verbose, it is still available as the source
+            Self::LockError(_) => write!(f, "The index is locked by another process"),
+        }
+    }
+}
+
+impl Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::DirectoryError(e) | Self::PermissionError(e) => Some(e),
+            Self::OpenError(e) => Some(e),
+            Self::CreateError(e) | Self::PutError(e) | Self::ReadError(e) => Some(e),
+            Self::CorruptIndex(e) | Self::LockError(e) => Some(e),
+            Self::QueryError(_, e) => Some(e),
+            Self::ParseError(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Translates the errors of the query parser, which are quite terse, into something that can be
+/// shown to the user
+fn query_error_hint(query: &str, e: &QueryParserError) -> String {
+    match e {
+        QueryParserError::SyntaxError => {
+            if let Some(field) = query
+                .split_whitespace()
+                .find(|w| w.ends_with(':') && !w.starts_with(':'))
+            {
+                let field = field.trim_start_matches(['(', '+', '-']);
+                format!(
+                    "Empty field value for '{}'. Try '{}something'.",
+                    field, field
+                )
+            } else if query.matches('(').count() != query.matches(')').count() {
+                format!("Unbalanced parentheses in '{}'.", query)
+            } else if !query.matches('"').count().is_multiple_of(2) {
+                format!("Unclosed quote in '{}'.", query)
+            } else {
+                format!("Invalid query syntax: '{}'.", query)
+            }
+        }
+        QueryParserError::FieldDoesNotExist(field) => format!(
+            "Unknown field '{}'. Valid fields are: {}.",
+            field,
+            DatabaseSchema::new()
+                .schema
+                .fields()
+                .map(|(_, entry)| entry.name())
+                .filter(|name| !INTERNAL_FIELDS.contains(name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        QueryParserError::FieldNotIndexed(field) => format!("Field '{}' can't be searched.", field),
+        QueryParserError::AllButQueryForbidden => String::from(
+            "Queries can't only exclude notes. Try adding '*' to the query, like '* -tag:foo'.",
+        ),
+        QueryParserError::DateFormatError(_) => String::from(
+            "Invalid date. Dates must be RFC3339 datetimes, like '2024-01-15T00:00:00Z'.",
+        ),
+        e => e.to_string(),
+    }
+}
+
+/// The outcome of `Database::put`, `Database::put_multiple` and `Database::reindex`
+#[derive(Debug, Default)]
+pub struct PutReport {
+    pub opstamp: Opstamp,
+    /// Number of notes that were not in the database yet
+    pub added: usize,
+    /// Number of notes that replaced an existing version of themselves
+    pub updated: usize,
+    /// The notes that could not be added, along with the reason why
+    pub failed: Vec<(String, DatabaseError)>,
+}
+
+/// The outcome of `Database::scan`, `Database::refresh` and `Database::update`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UpdateReport {
+    pub opstamp: Opstamp,
+    /// Number of notes that were not tracked yet
+    pub added: usize,
+    /// Number of tracked notes that changed
+    pub updated: usize,
+    /// Number of tracked notes that do not exist anymore
+    pub removed: usize,
+    /// Whether the scan stopped at its limit, or was cancelled, before adding all the new notes
+    pub truncated: bool,
+}
+
+/// The notes to add or update, and the paths to remove, found while updating the database
+#[derive(Default)]
+struct PendingChanges {
+    put: Vec<Zest>,
+    delete: Vec<String>,
+}
+
+/// Options of `DatabaseReader::query`, built from `SearchOptions::default()`
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    only_paths: bool,
+    limit: Option<usize>,
+    offset: usize,
+    score: bool,
+    since: Option<DateTime>,
+    boosts: Vec<(String, f32)>,
+}
+
+impl SearchOptions {
+    /// Only returns the paths of the notes, without reading them
+    pub fn only_paths(mut self, only_paths: bool) -> Self {
+        self.only_paths = only_paths;
+        self
+    }
+
+    /// Returns at most `limit` notes, instead of the configured `max_results`
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skips the `offset` most relevant notes, to get the next pages of results
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Returns the notes by decreasing relevance along with their score, instead of by path
+    pub fn score(mut self, score: bool) -> Self {
+        self.score = score;
+        self
+    }
+
+    /// Only returns the notes modified since `since`
+    pub fn since(mut self, since: DateTime) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Multiplies the relevance of the matches in `field` by `weight`, e.g. `boost("title", 2.0)`
+    /// ranks the notes matching by their title above the ones matching by their content. All the
+    /// fields have a weight of 1 by default.
+    pub fn boost(mut self, field: &str, weight: f32) -> Self {
+        self.boosts.push((field.to_owned(), weight));
+        self
+    }
+}
+
+/// A note matching a query, see `DatabaseReader::query`
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub path: String,
+    /// The note itself, unless only the paths were asked for
+    pub note: Option<Zest>,
+    /// The relevance of the note for the query, if it was asked for
+    pub score: Option<f32>,
+}
+
+/// The outcome of `DatabaseReader::query`
+#[derive(Debug, Clone, Default)]
+pub struct SearchResult {
+    pub hits: Vec<SearchHit>,
+    /// Number of notes matching the query, regardless of the limit and the offset
+    pub total: usize,
+}
+
+impl SearchResult {
+    /// The paths of the matching notes
+    pub fn paths(self) -> Vec<String> {
+        self.hits.into_iter().map(|h| h.path).collect()
+    }
+
+    /// The matching notes, if they were read
+    pub fn notes(self) -> Vec<Zest> {
+        self.hits.into_iter().filter_map(|h| h.note).collect()
+    }
+}
+
+/// The read-only part of a `Database`. It can be cloned and shared between threads to run
+/// queries concurrently, without touching the writer.
+#[derive(Clone)]
+pub struct DatabaseReader {
+    index: Index,
+    reader: IndexReader,
+    max_results: usize,
+}
+
+impl DatabaseReader {
+    fn searcher(&self) -> LeasedItem<Searcher> {
+        self.reader.searcher()
+    }
+
+    fn query_parser(&self, schema: &DatabaseSchema) -> QueryParser {
+        QueryParser::for_index(
+            &self.index,
+            vec![
+                schema.content,
+                schema.title,
+                schema.tag,
+                schema.reff,
+                schema.section,
+            ],
+        )
+    }
+
+    fn parse_query(
+        &self,
+        schema: &DatabaseSchema,
+        query: &str,
+    ) -> Result<Box<dyn Query>, DatabaseError> {
+        parse_query(&self.query_parser(schema), query)
+    }
+
+    /// Returns the notes matching `query`, at most `max_results` of them as configured. This is a
+    /// shorthand for `query` with the default options.
+    pub fn search(&self, query: String) -> Result<Vec<Zest>, DatabaseError> {
+        Ok(self.query(&query, SearchOptions::default())?.notes())
+    }
+
+    /// Same as `search`, but only returns the notes modified since `since`
+    pub fn search_since(
+        &self,
+        query: String,
+        since: &DateTime,
+    ) -> Result<Vec<Zest>, DatabaseError> {
+        let opts = SearchOptions::default().since(*since);
+        Ok(self.query(&query, opts)?.notes())
+    }
+
+    /// Same as `search`, but only returns the paths and the titles of the notes, as stored in the
+    /// index, instead of reading the notes again
+    pub fn search_fast(&self, query: String) -> Result<Vec<(String, String)>, DatabaseError> {
+        log::debug!("Searching with query: {}", query);
+        let schema = DatabaseSchema::new();
+        let searcher = self.searcher();
+        let q = self.parse_query(&schema, &query)?;
+
+        let docs = matching_docs(&searcher, &q, Some(self.max_results))?;
+        let mut returned = Vec::with_capacity(docs.len());
+        for doc_address in docs {
+            let doc = match fetch_doc(&searcher, doc_address) {
+                Some(doc) => doc,
+                None => continue,
+            };
+            let fname = doc
+                .get_first(schema.path)
+                .ok_or(DatabaseError::CorruptionError("missing path field"))?
+                .text()
+                .ok_or(DatabaseError::CorruptionError("wrong type for path field"))?
+                .to_string();
+            // The title comes first, before the other titles of the note
+            let title = doc
+                .get_first(schema.title)
+                .ok_or(DatabaseError::CorruptionError("missing title field"))?
+                .text()
+                .ok_or(DatabaseError::CorruptionError("wrong type for title field"))?
+                .to_string();
+            returned.push((fname, title));
+        }
+
+        // Sort by path so that the results are the same as the ones of `search`
+        returned.sort();
+        Ok(returned)
+    }
+
+    /// Returns the notes matching `query`, the most relevant first, as set by `opts`. Unless
+    /// they are scored, the returned notes are sorted by path.
+    pub fn query(&self, query: &str, opts: SearchOptions) -> Result<SearchResult, DatabaseError> {
+        log::debug!("Searching with query: {} ({:?})", query, opts);
+        let start = Instant::now();
+        let schema = DatabaseSchema::new();
+        let searcher = self.searcher();
+        let mut query_parser = self.query_parser(&schema);
+        for (field, weight) in &opts.boosts {
+            let f = schema.schema.get_field(field).ok_or_else(|| {
+                DatabaseError::QueryError(
+                    query.to_owned(),
+                    QueryParserError::FieldDoesNotExist(field.clone()),
+                )
+            })?;
+            query_parser.set_field_boost(f, *weight);
+        }
+        let q = modified_since(
+            &schema,
+            parse_query(&query_parser, query)?,
+            opts.since.as_ref(),
+        );
+
+        let limit = opts.limit.unwrap_or(self.max_results);
+        let (top_docs, total) = if limit == 0 {
+            // TopDocs can't collect nothing, only count the matching notes then
+            let total = searcher
+                .search(&q, &Count)
+                .map_err(|e| DatabaseError::ReadError(e))?;
+            (Vec::new(), total)
+        } else {
+            let collector = TopDocs::with_limit(limit).and_offset(opts.offset);
+            searcher
+                .search(&q, &(collector, Count))
+                .map_err(|e| DatabaseError::ReadError(e))?
+        };
+        if opts.limit.is_none() && total > limit {
+            log::warn!(
+                "{} notes match the query, only keeping the first {}",
+                total,
+                limit
+            );
+        }
+        log::debug!(
+            target: PROFILE_TARGET,
+            "Found {} notes in {}ms",
+            top_docs.len(),
+            start.elapsed().as_millis()
+        );
+
+        let mut hits = Vec::with_capacity(top_docs.len());
+        for (score, doc_address) in top_docs {
+            let doc = match fetch_doc(&searcher, doc_address) {
+                Some(doc) => doc,
+                None => continue,
+            };
+            let path = doc
+                .get_first(schema.path)
+                .ok_or(DatabaseError::CorruptionError("missing path field"))?
+                .text()
+                .ok_or(DatabaseError::CorruptionError("wrong type for path field"))?
+                .to_string();
+            let note = if opts.only_paths {
+                None
+            } else {
+                match read_indexed_note(path.clone()) {
+                    Some(mut z) => {
+                        z.backlink_count = backlink_count(&searcher, &schema, &path)?;
+                        Some(z)
+                    }
+                    None => continue,
+                }
+            };
+            hits.push(SearchHit {
+                path,
+                note,
+                score: opts.score.then_some(score),
+            });
+        }
+
+        if !opts.score {
+            hits.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        log::debug!(
+            target: PROFILE_TARGET,
+            "Search in {}ms",
+            start.elapsed().as_millis()
+        );
+        Ok(SearchResult { hits, total })
+    }
+
+    /// Suggests completions for the last word of a query being typed, based on the terms of the
+    /// titles and the tags of the notes. The most frequent terms come first.
+    pub fn suggest_query(&self, partial: &str) -> Result<Vec<String>, DatabaseError> {
+        const MAX_SUGGESTIONS: usize = 10;
+
+        let schema = DatabaseSchema::new();
+        let (fields, prefix) = match partial.split_once(':') {
+            Some((TITLE_FIELD, p)) => (vec![(schema.title, Some(TITLE_FIELD))], p),
+            Some((TAG_FIELD, p)) => (vec![(schema.tag, Some(TAG_FIELD))], p),
+            _ => (
+                vec![(schema.title, None), (schema.tag, Some(TAG_FIELD))],
+                partial,
+            ),
+        };
+
+        let searcher = self.searcher();
+        let mut freqs: HashMap<String, u32> = HashMap::new();
+        for (field, field_name) in fields {
+            // Titles are lowercased by the tokenizer, but tags are not
+            let prefix = if field == schema.title {
+                prefix.to_lowercase()
+            } else {
+                prefix.to_owned()
+            };
+
+            terms_with_prefix(&searcher, field, &prefix, |term, doc_freq| {
+                let suggestion = match field_name {
+                    Some(name) => format!("{}:{}", name, term),
+                    None => term.to_owned(),
+                };
+                *freqs.entry(suggestion).or_insert(0) += doc_freq;
+            })?;
+        }
+
+        let mut suggestions: Vec<(String, u32)> = freqs.into_iter().collect();
+        suggestions.sort_by(|(a, a_freq), (b, b_freq)| b_freq.cmp(a_freq).then(a.cmp(b)));
+        Ok(suggestions
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(s, _)| s)
+            .collect())
+    }
+
+    /// Returns the tags starting with `prefix`, in alphabetical order, with the number of notes
+    /// having each of them
+    pub fn tags_starting_with(&self, prefix: &str) -> Result<Vec<(String, usize)>, DatabaseError> {
+        let schema = DatabaseSchema::new();
+        let searcher = self.searcher();
+        let mut tags: BTreeMap<String, usize> = BTreeMap::new();
+        terms_with_prefix(&searcher, schema.tag, prefix, |tag, doc_freq| {
+            *tags.entry(tag.to_owned()).or_insert(0) += doc_freq as usize;
+        })?;
+        Ok(tags.into_iter().collect())
+    }
+
+    /// Returns the paths of the notes matching `query`, at most `max_results` of them. This is a
+    /// shorthand for `query` with `SearchOptions::only_paths`, prefer it in new code.
+    pub fn list(&self, query: String) -> Result<Vec<String>, DatabaseError> {
+        let opts = SearchOptions::default().only_paths(true);
+        Ok(self.query(&query, opts)?.paths())
+    }
+
+    /// Calls `f` with each note matching `query`, by path, reading the notes one at a time instead
+    /// of collecting them first. Unlike `search`, all the matching notes are visited, regardless of
+    /// the `max_results` setting.
+    pub fn for_each<F>(&self, query: &str, f: F) -> Result<(), DatabaseError>
+    where
+        F: FnMut(Zest),
+    {
+        let schema = DatabaseSchema::new();
+        let q = self.parse_query(&schema, query)?;
+        self.for_each_matching(&schema, &q, f)
+    }
+
+    /// Same as `for_each`, with an already parsed query
+    fn for_each_matching<F>(
+        &self,
+        schema: &DatabaseSchema,
+        query: &dyn Query,
+        mut f: F,
+    ) -> Result<(), DatabaseError>
+    where
+        F: FnMut(Zest),
+    {
+        // Release the searcher before calling `f`, which may need one
+        let paths = matching_paths(&self.searcher(), schema, query, None)?;
+        for z in paths.into_iter().filter_map(read_indexed_note) {
+            f(z);
+        }
+        Ok(())
+    }
+
+    /// Returns the paths of all the notes of the database, regardless of the `max_results` setting
+    pub fn list_all(&self) -> Result<Vec<String>, DatabaseError> {
+        let schema = DatabaseSchema::new();
+        let searcher = self.searcher();
+        matching_paths(&searcher, &schema, &AllQuery, None)
+    }
+
+    /// Same as `list`, but only returns the notes modified since `since`
+    pub fn list_since(
+        &self,
+        query: String,
+        since: &DateTime,
+    ) -> Result<Vec<String>, DatabaseError> {
+        let opts = SearchOptions::default().only_paths(true).since(*since);
+        Ok(self.query(&query, opts)?.paths())
+    }
+
+    /// Returns the number of notes matching `query`, regardless of the `max_results` setting
+    pub fn count_results(&self, query: String) -> Result<usize, DatabaseError> {
+        let schema = DatabaseSchema::new();
+        let searcher = self.searcher();
+        let q = self.parse_query(&schema, &query)?;
+        searcher
+            .search(&q, &Count)
+            .map_err(|e| DatabaseError::ReadError(e))
+    }
+
+    /// Returns all the notes that link to the note at `path`
+    #[cfg(feature = "graph")]
+    pub fn find_backlinks(&self, path: &str) -> Result<Vec<Zest>, DatabaseError> {
+        log::debug!("Finding backlinks of {}", path);
+        let schema = DatabaseSchema::new();
+        let searcher = self.searcher();
+        let query = TermQuery::new(
+            Term::from_field_text(schema.link, path),
+            IndexRecordOption::Basic,
+        );
+        Ok(matching_paths(&searcher, &schema, &query, None)?
+            .into_iter()
+            .filter_map(read_indexed_note)
+            .collect())
+    }
+}
+
+pub struct Database {
+    config: Config,
+    writer: Option<IndexWriter>,
+    reader: DatabaseReader,
+    /// Set to stop looking for new files, see `set_cancel_flag`
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Database {
+    /// Opens the database configured in the XDG directories, creating the index if needed
+    pub fn open() -> Result<Self, DatabaseError> {
+        Self::open_or_create_at(None, None, false)
+    }
+
+    /// Opens the database without acquiring the writer lock, so that it can be used alongside
+    /// another process updating the index. Any method modifying the index will fail with
+    /// `DatabaseError::ReadOnly`.
+    pub fn open_readonly() -> Result<Self, DatabaseError> {
+        Self::open_or_create_at(None, None, true)
+    }
+
+    /// Deletes the index, which can't be read anymore after a `DatabaseError::SchemaMismatch`, and
+    /// creates an empty one in its place. The notes of the configured paths can then be added
+    /// back with `update`.
+    pub fn recreate_at(
+        index_dir: Option<&Path>,
+        config_file: Option<&Path>,
+    ) -> Result<Self, DatabaseError> {
+        let dir = Self::index_dir(index_dir)?;
+        log::info!("Deleting the index in {}", dir.display());
+        std::fs::remove_dir_all(&dir).map_err(index_dir_error)?;
+        Self::open_or_create_at(Some(&dir), config_file, false)
+    }
+
+    /// Returns the configuration file `open_or_create_at` reads, which is `config_file` when
+    /// specified
+    pub fn config_file(config_file: Option<&Path>) -> Option<PathBuf> {
+        config_file
+            .map(Path::to_path_buf)
+            .or_else(|| default_config_file(&XDG_DIR).ok())
+    }
+
+    /// Creates the directory of the index if needed, and returns it
+    fn index_dir(index_dir: Option<&Path>) -> Result<PathBuf, DatabaseError> {
+        if let Some(dir) = index_dir {
+            log::trace!("Open {}", dir.display());
+            std::fs::create_dir_all(dir).map_err(index_dir_error)?;
+            Ok(dir.to_path_buf())
+        } else {
+            log::trace!("Open XDG directory");
+            default_index_dir(&XDG_DIR).map_err(index_dir_error)
+        }
+    }
+
+    /// Opens the database, using the index stored in `index_dir` and the configuration file
+    /// `config_file` instead of the XDG ones when specified.
+    pub fn open_or_create_at(
+        index_dir: Option<&Path>,
+        config_file: Option<&Path>,
+        readonly: bool,
+    ) -> Result<Self, DatabaseError> {
+        let dir = Self::index_dir(index_dir)?;
+
+        log::trace!("Open index");
+        let start = Instant::now();
+        let dir = MmapDirectory::open(dir).map_err(|e| DatabaseError::OpenError(e))?;
+        let index =
+            Index::open_or_create(dir, DatabaseSchema::new().schema).map_err(open_index_error)?;
+        log::debug!(target: PROFILE_TARGET, "Index opened in {}ms", start.elapsed().as_millis());
+        check_index_version(&index)?;
+
+        let writer = if readonly {
+            log::trace!("Skip writer creation, database is read-only");
+            None
+        } else {
+            log::trace!("Create writer");
+            Some(index.writer(writer_budget()).map_err(open_index_error)?)
+        };
+
+        log::trace!("Create reader");
+        let reader = index.reader().map_err(open_index_error)?;
+
+        log::debug!("Open configuration");
+        let mut config: Config = if let Some(conffile) = config_file {
+            // The user explicitly asked for this file, so don't silently ignore it
+            let conffile = File::open(conffile).map_err(|e| {
+                DatabaseError::ConfigError(format!("{}: {}", conffile.display(), e))
+            })?;
+            serde_yaml::from_reader(BufReader::new(conffile))
+                .map_err(|e| DatabaseError::ConfigError(e.to_string()))?
+        } else {
+            let conffile =
+                default_config_file(&XDG_DIR).map_err(|e| DatabaseError::DirectoryError(e))?;
+            if let Ok(conffile) = File::open(conffile) {
+                let conffile = BufReader::new(conffile);
+                if let Ok(c) = serde_yaml::from_reader(conffile) {
+                    c
+                } else {
+                    Config::default()
+                }
+            } else {
+                Config::default()
+            }
+        };
+
+        config.expand_paths()?;
+        log::debug!("Using config : {:?}", config);
+        let max_results = config.max_results;
+
+        Ok(Database {
+            config,
+            cancel: None,
+            writer,
+            reader: DatabaseReader {
+                index,
+                reader,
+                max_results,
+            },
+        })
+    }
+
+    /// Returns a reader of the database, that can be used from other threads
+    pub fn reader(&self) -> DatabaseReader {
+        self.reader.clone()
+    }
+
+    /// Commits the pending changes, and releases the writer, its memory and its lock, once they
+    /// are done merging the segments of the index. Readers obtained with `reader` can still be
+    /// used afterwards, to keep searching the index without holding the writer.
+    pub fn close(mut self) -> Result<(), DatabaseError> {
+        if self.writer.is_some() {
+            self.commit()?;
+        }
+        if let Some(writer) = self.writer.take() {
+            log::trace!("Wait for the merges to finish");
+            writer
+                .wait_merging_threads()
+                .map_err(|e| DatabaseError::PutError(e))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the opstamp of the last commit of the index, by any process. It changes with each
+    /// commit, so that it can be used to know whether cached results are outdated.
+    pub fn current_opstamp(&self) -> Result<Opstamp, DatabaseError> {
+        self.reader
+            .index
+            .load_metas()
+            .map(|metas| metas.opstamp)
+            .map_err(|e| DatabaseError::ReadError(e))
+    }
+
+    /// See `DatabaseReader::search`
+    pub fn search(&self, query: String) -> Result<Vec<Zest>, DatabaseError> {
+        self.reader.search(query)
+    }
+
+    /// See `DatabaseReader::query`
+    pub fn query(&self, query: &str, opts: SearchOptions) -> Result<SearchResult, DatabaseError> {
+        self.reader.query(query, opts)
+    }
+
+    /// See `DatabaseReader::search_fast`
+    pub fn search_fast(&self, query: String) -> Result<Vec<(String, String)>, DatabaseError> {
+        self.reader.search_fast(query)
+    }
+
+    /// See `DatabaseReader::suggest_query`
+    pub fn suggest_query(&self, partial: &str) -> Result<Vec<String>, DatabaseError> {
+        self.reader.suggest_query(partial)
+    }
+
+    /// Returns all the notes of the database, regardless of the `max_results` setting
+    fn all(&self) -> Result<Vec<Zest>, DatabaseError> {
+        Ok(self
+            .list_all()?
+            .into_iter()
+            .filter_map(read_indexed_note)
+            .collect())
+    }
+
+    /// See `DatabaseReader::tags_starting_with`
+    pub fn tags_starting_with(&self, prefix: &str) -> Result<Vec<(String, usize)>, DatabaseError> {
+        self.reader.tags_starting_with(prefix)
+    }
+
+    /// See `DatabaseReader::list`
+    pub fn list(&self, query: String) -> Result<Vec<String>, DatabaseError> {
+        self.reader.list(query)
+    }
+
+    /// See `DatabaseReader::for_each`
+    pub fn for_each<F>(&self, query: &str, f: F) -> Result<(), DatabaseError>
+    where
+        F: FnMut(Zest),
+    {
+        self.reader.for_each(query, f)
+    }
+
+    /// See `DatabaseReader::list_all`
+    pub fn list_all(&self) -> Result<Vec<String>, DatabaseError> {
+        self.reader.list_all()
+    }
+
+    /// See `DatabaseReader::count_results`
+    pub fn count_results(&self, query: String) -> Result<usize, DatabaseError> {
+        self.reader.count_results(query)
+    }
+
+    /// See `DatabaseReader::find_backlinks`
+    #[cfg(feature = "graph")]
+    pub fn find_backlinks(&self, path: &str) -> Result<Vec<Zest>, DatabaseError> {
+        self.reader.find_backlinks(path)
+    }
+
+    fn writer(&mut self) -> Result<&mut IndexWriter, DatabaseError> {
+        self.writer.as_mut().ok_or(DatabaseError::ReadOnly)
+    }
+
+    /// Adds `z` to the database, replacing its previous version if any, and resolving its links
+    /// with `searcher`. Lease it once for all the notes: the reader may only have a single searcher
+    /// to lend. Returns whether there was a previous version, or a `DirectoryError` if the file
+    /// disappeared since `z` was parsed.
+    fn put_doc(
+        &mut self,
+        z: Zest,
+        schema: &DatabaseSchema,
+        searcher: &Searcher,
+    ) -> Result<bool, DatabaseError> {
+        log::debug!("Inserting {:?}", z);
+        // The links are checked when resolving them, through the index
+        for warning in z.validate_metadata() {
+            log::warn!("{}", warning);
+        }
+        let title = z.title.clone();
+        let (fname, doc) = self.note_document(z, schema, searcher)?;
+
+        let path_term = Term::from_field_text(schema.path, &fname);
+        let existing = searcher
+            .search(
+                &TermQuery::new(path_term.clone(), IndexRecordOption::Basic),
+                &Count,
+            )
+            .map_err(|e| DatabaseError::ReadError(e))?;
+
+        log::trace!("Remove previously existing entries");
+        self.writer()?.delete_term(path_term);
+
+        log::debug!("Adding {:?}", doc);
+        self.writer()?.add_document(doc);
+
+        let action = if existing > 0 { "update" } else { "add" };
+        log::info!(
+            action = action, path = fname.as_str(), title = title.as_str();
+            "{} {}", action, fname
+        );
+        Ok(existing > 0)
+    }
+
+    /// Builds the document indexing `z`, along with the canonical path of the note
+    fn note_document(
+        &self,
+        mut z: Zest,
+        schema: &DatabaseSchema,
+        searcher: &Searcher,
+    ) -> Result<(String, Document), DatabaseError> {
+        if self.config.inline_tags {
+            z.use_inline_tags();
+        }
+        let fname = std::fs::canonicalize(&z).map_err(|e| DatabaseError::DirectoryError(e))?;
+        let fname = fname.to_str().unwrap();
+
+        let metadata = std::fs::metadata(&fname).map_err(|e| DatabaseError::DirectoryError(e))?;
+        let mut doc = Document::new();
+
+        if let Ok(time) = metadata.modified() {
+            let time = DateTime::from(time);
+            log::trace!("Creating {} with modified time of {}", fname, time);
+            doc.add_date(schema.last_modif, &time);
+        } else {
+            log::warn!("Could not retrieve {} last modified date.", fname);
+        }
+        match hash_file(fname) {
+            Ok(hash) => doc.add_text(schema.hash, hash),
+            Err(e) => log::warn!("Could not compute {} hash: {}", fname, e),
+        }
+        if let Some(created_at) = z.created_at {
+            doc.add_date(schema.created_at, &created_at);
+        }
+        for tag in z.tags() {
+            doc.add_text(schema.tag, tag);
+        }
+
+        doc.add_text(schema.title, z.title);
+        for alias in z.alt_titles.into_iter().chain(z.metadata.aliases) {
+            doc.add_text(schema.title, alias);
+        }
+        doc.add_text(schema.file, fname.to_owned());
+        // Also index the file name alone, to search notes by name regardless of their directory
+        if let Some(stem) = Path::new(fname).file_stem().and_then(|s| s.to_str()) {
+            doc.add_text(schema.file, stem.to_owned());
+        }
+        doc.add_text(schema.path, fname.to_owned());
+        // Markup the parser leaves in the text, like unbalanced emphasis markers, needs no
+        // stripping: the tokenizer splits on punctuation, both when indexing and when querying
+        doc.add_text(schema.content, z.content);
+        for section in z.sections {
+            doc.add_text(schema.section, section.heading);
+        }
+        for code in z.code_blocks.into_iter().chain(z.inline_code) {
+            doc.add_text(schema.code, code);
+        }
+
+        let query_parser = self.reader.query_parser(schema);
+        let dir = Path::new(fname).parent().unwrap_or_else(|| Path::new(""));
+        for reff in z.refs {
+            match resolve_ref(searcher, &query_parser, schema, dir, &reff) {
+                Ok(matching) if matching.is_empty() && !reff.is_empty() => {
+                    log::warn!("{} links to {}, which does not exist", fname, reff)
+                }
+                Ok(matching) => {
+                    for matching in matching {
+                        log::info!("{} references {}", fname, matching);
+                        doc.add_text(schema.reff, &matching);
+                        doc.add_text(schema.link, matching);
+                    }
+                }
+                Err(e) => log::warn!("Could not resolve {} link {}: {}", fname, reff, e),
+            }
+        }
+
+        Ok((fname.to_owned(), doc))
+    }
+
+    /// Commits the pending changes and reloads the reader so that they are visible. This is
+    /// internal: each public method modifying the index commits its own changes, so that it
+    /// never stays half updated.
+    fn commit(&mut self) -> Result<Opstamp, DatabaseError> {
+        let start = Instant::now();
+        let mut prepared = self
+            .writer()?
+            .prepare_commit()
+            .map_err(|e| DatabaseError::PutError(e))?;
+        // Record the version of tantivy that wrote the index, see `check_index_version`
+        prepared.set_payload(tantivy::version_string());
+        let op = prepared.commit().map_err(|e| DatabaseError::PutError(e))?;
+        log::debug!(target: PROFILE_TARGET, "Commit in {}ms", start.elapsed().as_millis());
+
+        // The changes are committed at this point, failing to reload would only leave the reader
+        // showing the previous state of the index until the next commit, so try again a few times
+        let mut attempt = 1;
+        loop {
+            match self.reader.reader.reload() {
+                Ok(_) => return Ok(op),
+                Err(e) if attempt < RELOAD_ATTEMPTS => {
+                    log::warn!(
+                        "Could not reload the index (attempt {} of {}): {}",
+                        attempt,
+                        RELOAD_ATTEMPTS,
+                        e
+                    );
+                    std::thread::sleep(RELOAD_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => return Err(DatabaseError::PutError(e)),
+            }
+        }
+    }
+
+    /// Adds `z` to the database, replacing its previous version if any
+    pub fn put(&mut self, z: Zest) -> Result<PutReport, DatabaseError> {
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let updated = self.put_doc(z, &schema, &searcher)?;
+        drop(searcher);
+
+        Ok(PutReport {
+            opstamp: self.commit()?,
+            added: if updated { 0 } else { 1 },
+            updated: if updated { 1 } else { 0 },
+            failed: Vec::new(),
+        })
+    }
+
+    /// Adds all the notes of `zs` to the database. A note that can't be added does not prevent the
+    /// others from being added, and is reported in the returned `PutReport`.
+    pub fn put_multiple(&mut self, zs: Vec<Zest>) -> Result<PutReport, DatabaseError> {
+        // Don't report every single note as failed when the database is read-only
+        self.writer()?;
+
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let mut report = self.put_multiple_unchecked(zs, &schema, &searcher);
+        drop(searcher);
+
+        report.opstamp = self.commit()?;
+        Ok(report)
+    }
+
+    /// Same as `put_multiple`, but leaves the changes uncommitted, for the caller to commit them
+    /// along with its own
+    fn put_multiple_unchecked(
+        &mut self,
+        zs: Vec<Zest>,
+        schema: &DatabaseSchema,
+        searcher: &Searcher,
+    ) -> PutReport {
+        let start = Instant::now();
+        let count = zs.len();
+        let mut report = PutReport::default();
+        for z in zs {
+            let fname = z.file.clone();
+            match self.put_doc(z, schema, searcher) {
+                Ok(true) => report.updated += 1,
+                Ok(false) => report.added += 1,
+                Err(e) => {
+                    log::debug!("Could not put {}: {}", fname, e);
+                    report.failed.push((fname, e));
+                }
+            }
+        }
+        log::debug!(
+            target: PROFILE_TARGET,
+            "Indexed {} documents in {}ms",
+            count - report.failed.len(),
+            start.elapsed().as_millis()
+        );
+        report
+    }
+
+    /// Removes the notes matching `query` from the database. The files themselves are kept.
+    pub fn remove(&mut self, query: String) -> Result<Opstamp, DatabaseError> {
+        log::debug!("Removing with query: {}", query);
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+
+        log::trace!("Parse query");
+        let q = self.reader.parse_query(&schema, &query)?;
+
+        let to_execute = searcher
+            .search(&q, &DocSetCollector)
+            .map_err(|e| DatabaseError::ReadError(e))?
+            .iter()
+            .filter_map(|doc_address| {
+                let doc = fetch_doc(&searcher, *doc_address)?;
+                let fname_field = if let Some(f) = doc.get_first(schema.path) {
+                    f
+                } else {
+                    log::debug!("{:?} has no path field ?", doc_address);
+                    return None;
+                };
+
+                let fname = if let Some(f) = fname_field.text() {
+                    f
+                } else {
+                    log::debug!("{:?} path field is not of the correct type ?", doc_address);
+                    return None;
+                };
+
+                log::info!(action = "remove", path = fname; "remove {}", fname);
+                Some(UserOperation::Delete(Term::from_field_text(
+                    schema.path,
+                    fname,
+                )))
+            })
+            .collect();
+        self.writer()?.run(to_execute);
+        self.commit()
+    }
+
+    /// Looks for the untracked notes of the configured paths, and adds them to `changes`
+    fn check_new(
+        &self,
+        schema: &DatabaseSchema,
+        searcher: &Searcher,
+        limit: Option<usize>,
+        changes: &mut PendingChanges,
+        report: &mut UpdateReport,
+    ) -> Result<(), DatabaseError> {
+        let start = Instant::now();
+        let mut new_docs: Vec<Zest> = Vec::new();
+        let mut dir_count = 0;
+        let mut file_count = 0;
+        'paths: for path in &self.config.paths {
+            log::trace!("Looking into {}", path);
+            if let Ok(dmeta) = std::fs::metadata(path) {
+                if !dmeta.is_dir() {
+                    log::warn!("{} is not a directory.", path);
+                    continue;
+                }
+
+                dir_count += 1;
+                let dir = match std::fs::canonicalize(path) {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        log::warn!("Could not look into {}: {}", path, e);
+                        continue;
+                    }
+                };
+                for entry in Zest::walk_directory(&dir, self.follow_symlinks()) {
+                    if self.cancelled() {
+                        log::info!("Scan cancelled after {} new files", new_docs.len());
+                        report.truncated = true;
+                        break 'paths;
+                    }
+
+                    file_count += 1;
+                    // The file may have been removed since the directory was walked
+                    let entry = match std::fs::canonicalize(&entry) {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            log::debug!("Skipping {}: {}", entry, e);
+                            continue;
+                        }
+                    };
+                    let entry = entry.to_str().unwrap();
+                    log::trace!("Checking {}", entry);
+                    let query = TermQuery::new(
+                        Term::from_field_text(schema.path, entry),
+                        IndexRecordOption::Basic,
+                    );
+
+                    let tracked = searcher
+                        .search(&query, &Count)
+                        .map_err(|e| DatabaseError::ReadError(e))?;
+                    if tracked == 0 {
+                        if limit.is_some_and(|limit| new_docs.len() >= limit) {
+                            log::info!("Stopping the scan after {} new files", new_docs.len());
+                            report.truncated = true;
+                            break 'paths;
+                        }
+
+                        // This file is not tracked yet, track it then
+                        log::debug!("{} is not tracked yet, adding it", entry);
+                        match self.parse_file(entry.to_owned()) {
+                            Ok(z) => new_docs.push(z),
+                            Err(ZestParsingError::BinaryFile(_)) => {
+                                log::info!("Skipping binary file {}", entry)
+                            }
+                            Err(e) => log::warn!("Could not parse {}: {}", entry, e),
+                        }
+                    }
+                }
+            }
+        }
+        log::info!(
+            "Scan complete: {} new files found among {} files across {} directories",
+            new_docs.len(),
+            file_count,
+            dir_count
+        );
+        log::debug!(
+            target: PROFILE_TARGET,
+            "check_new found {} files in {}ms",
+            new_docs.len(),
+            start.elapsed().as_millis()
+        );
+        changes.put.extend(new_docs);
+        Ok(())
+    }
+
+    /// Looks for the tracked files that changed since they were indexed, and the ones that do not
+    /// exist anymore, and adds them to `changes`
+    fn check_changed(
+        &self,
+        schema: &DatabaseSchema,
+        searcher: &Searcher,
+        changes: &mut PendingChanges,
+    ) -> Result<(), DatabaseError> {
+        let start = Instant::now();
+        let mut tracked = Vec::new();
+        let docs = searcher
+            .search(&AllQuery, &DocSetCollector)
+            .map_err(|e| DatabaseError::ReadError(e))?;
+        for doc_address in docs {
+            let doc = match fetch_doc(&searcher, doc_address) {
+                Some(doc) => doc,
+                None => continue,
+            };
+            let fname = doc
+                .get_first(schema.path)
+                .ok_or(DatabaseError::CorruptionError("missing path field"))?
+                .text()
+                .ok_or(DatabaseError::CorruptionError("wrong type for path field"))?
+                .to_string();
+            let changetime = *doc
+                .get_first(schema.last_modif)
+                .ok_or(DatabaseError::CorruptionError("missing file last_modified"))?
+                .date_value()
+                .ok_or(DatabaseError::CorruptionError(
+                    "wrong type for last_modif field",
+                ))?;
+            let hash = doc
+                .get_first(schema.hash)
+                .and_then(|h| h.text())
+                .map(String::from);
+            tracked.push((fname, changetime, hash));
+        }
+        let checked = tracked.len();
+
+        // Only the stored timestamp and hash are needed to tell whether a note changed, the notes
+        // themselves are only parsed when they did, as their content can't be read from the index.
+        // Checking the files is mostly waiting for the filesystem, do it in parallel and only
+        // write to the index sequentially
+        let max_size = self.max_file_size();
+        let statuses: Vec<(String, FileStatus)> = tracked
+            .into_par_iter()
+            .map(|(fname, changetime, hash)| {
+                let status = file_status(&fname, &changetime, hash.as_deref(), max_size);
+                (fname, status)
+            })
+            .collect();
+
+        for (fname, status) in statuses {
+            match status {
+                FileStatus::Unchanged => log::debug!(
+                    action = "skip", path = fname.as_str(), reason = "unchanged";
+                    "SKIP {} (unchanged)", fname
+                ),
+                FileStatus::Changed(reason, parsed) => match *parsed {
+                    Ok(z) => {
+                        log::debug!(
+                            action = "update", path = fname.as_str(), reason = reason;
+                            "UPDATE {} ({})", fname, reason
+                        );
+                        changes.put.push(z);
+                    }
+                    Err(e) => log::warn!("Could not update {}: {}", fname, e),
+                },
+                FileStatus::Deleted(reason) => {
+                    log::debug!(
+                        action = "remove", path = fname.as_str(), reason = reason;
+                        "REMOVE {} ({})", fname, reason
+                    );
+                    changes.delete.push(fname);
+                }
+            }
+        }
+        log::debug!(
+            target: PROFILE_TARGET,
+            "check_changed found {} changes among {} documents in {}ms",
+            changes.put.len() + changes.delete.len(),
+            checked,
+            start.elapsed().as_millis()
+        );
+        Ok(())
+    }
+
+    /// Applies the changes found by `check_new` and `check_changed` as a single batch, counting
+    /// them in `report`. They are left uncommitted, so that the caller commits them all at once.
+    fn apply_changes(
+        &mut self,
+        changes: PendingChanges,
+        schema: &DatabaseSchema,
+        searcher: &Searcher,
+        report: &mut UpdateReport,
+    ) -> Result<(), DatabaseError> {
+        // Fail early instead of reporting every single note as failed
+        self.writer()?;
+
+        for fname in changes.delete {
+            log::info!(action = "remove", path = fname.as_str(); "remove {}", fname);
+            self.writer()?
+                .delete_term(Term::from_field_text(schema.path, &fname));
+            report.removed += 1;
+        }
+
+        let put = self.put_multiple_unchecked(changes.put, schema, searcher);
+        for (fname, e) in put.failed {
+            match e {
+                // The file was removed since it was found, the next update takes care of it
+                DatabaseError::DirectoryError(e) => log::warn!("Could not add {}: {}", fname, e),
+                e => return Err(e),
+            }
+        }
+        report.added += put.added;
+        report.updated += put.updated;
+        Ok(())
+    }
+
+    /// Whether the note at `path` is indexed with its current content
+    pub fn is_up_to_date(&self, path: &str) -> Result<bool, DatabaseError> {
+        let path = std::fs::canonicalize(path).map_err(|e| DatabaseError::DirectoryError(e))?;
+        let path = path.to_str().unwrap();
+
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(
+            Term::from_field_text(schema.path, path),
+            IndexRecordOption::Basic,
+        );
+        let docs = matching_docs(&searcher, &query, Some(1))?;
+        let doc = match docs.first() {
+            Some(address) => searcher
+                .doc(*address)
+                .map_err(|e| DatabaseError::ReadError(e))?,
+            None => return Ok(false),
+        };
+
+        match doc.get_first(schema.hash).and_then(|h| h.text()) {
+            Some(hash) => Ok(hash_file(path).map(|h| h == hash).unwrap_or(false)),
+            None => Ok(false),
+        }
+    }
+
+    /// The size above which notes are not indexed, if any
+    pub fn max_file_size(&self) -> Option<u64> {
+        self.config.max_file_size_bytes
+    }
+
+    /// Whether symbolic links are followed when looking for notes in directories
+    pub fn follow_symlinks(&self) -> bool {
+        self.config.follow_symlinks
+    }
+
+    /// Sets the flag stopping the scans of the configured paths once it is set, e.g. when the user
+    /// hits Ctrl-C. The new files found until then are still added.
+    pub fn set_cancel_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.cancel = Some(flag);
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Parses the note at `path`, honoring the `max_file_size_bytes` setting
+    fn parse_file(&self, path: String) -> Result<Zest, ZestParsingError> {
+        Zest::from_file_with_limit(path, self.max_file_size())
+    }
+
+    /// Looks for untracked files in the configured paths, and adds them to the database
+    pub fn scan(&mut self) -> Result<UpdateReport, DatabaseError> {
+        self.scan_with_limit(None)
+    }
+
+    /// Same as `scan`, but stops after adding `limit` new files, if set. The next scans add the
+    /// remaining ones.
+    pub fn scan_with_limit(&mut self, limit: Option<usize>) -> Result<UpdateReport, DatabaseError> {
+        log::debug!("Scan start");
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let mut report = UpdateReport::default();
+        let mut changes = PendingChanges::default();
+        self.check_new(&schema, &searcher, limit, &mut changes, &mut report)?;
+        self.apply_changes(changes, &schema, &searcher, &mut report)?;
+        drop(searcher);
+        report.opstamp = self.commit()?;
+        Ok(report)
+    }
+
+    /// Updates the changed files and removes the deleted ones from the database, without looking
+    /// for new files
+    pub fn refresh(&mut self) -> Result<UpdateReport, DatabaseError> {
+        log::debug!("Refresh start");
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let mut report = UpdateReport::default();
+        let mut changes = PendingChanges::default();
+        self.check_changed(&schema, &searcher, &mut changes)?;
+        self.apply_changes(changes, &schema, &searcher, &mut report)?;
+        drop(searcher);
+        report.opstamp = self.commit()?;
+        Ok(report)
+    }
+
+    /// Synchronizes the database with the configured paths, this is a `scan` followed by a
+    /// `refresh`
+    pub fn update(&mut self) -> Result<UpdateReport, DatabaseError> {
+        self.update_with_limit(None)
+    }
+
+    /// Same as `update`, but adds at most `limit` new files, if set
+    pub fn update_with_limit(
+        &mut self,
+        limit: Option<usize>,
+    ) -> Result<UpdateReport, DatabaseError> {
+        log::debug!("Update start");
+        let start = Instant::now();
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let mut report = UpdateReport::default();
+        let mut changes = PendingChanges::default();
+        self.check_new(&schema, &searcher, limit, &mut changes, &mut report)?;
+        self.check_changed(&schema, &searcher, &mut changes)?;
+        self.apply_changes(changes, &schema, &searcher, &mut report)?;
+        drop(searcher);
+        report.opstamp = self.commit()?;
+        log::debug!(
+            target: PROFILE_TARGET,
+            "Update in {}ms",
+            start.elapsed().as_millis()
+        );
+        Ok(report)
+    }
+
+    /// Creates a new file titled `title`, or "Untitled", with `meta` as its metadata header, adds it
+    /// to the database, and returns it's full path along with the parsed note
+    pub fn create(
+        &mut self,
+        title: Option<&str>,
+        meta: &ZestMeta,
+    ) -> Result<(String, Zest, Opstamp), DatabaseError> {
+        if self.config.paths.is_empty() {
+            return Err(DatabaseError::ConfigError(String::from(
+                "The config does not specify paths",
+            )));
+        }
+        let curtime = DateTime::from(std::time::SystemTime::now());
+        let root = std::fs::canonicalize(self.config.paths.get(0).unwrap()).unwrap();
+        let mut p = PathBuf::from(root);
+        let template = self
+            .config
+            .filename_template
+            .as_deref()
+            .unwrap_or(DEFAULT_FILENAME_TEMPLATE);
+        p.push(note_file_name(template, &curtime)?);
+
+        let p = p.to_str().unwrap();
+        // The serialized metadata starts with the opening line of the header
+        let header = serde_yaml::to_string(meta).map_err(|e| {
+            DatabaseError::ParseError(p.to_owned(), ZestParsingError::MetadataError(e.to_string()))
+        })?;
+        let content = format!("{}---\n\n# {}\n\n", header, title.unwrap_or("Untitled"));
+        // Never overwrite a note created during the same second
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(p)
+            .and_then(|mut f| f.write_all(content.as_bytes()))
+            .map_err(|e| DatabaseError::DirectoryError(e))?;
+        let z = if let Ok(z) = Zest::from_file(p.to_owned()) {
+            z
+        } else {
+            unreachable!("zest should consider new notes as valid")
+        };
+
+        let report = self.put(z.clone())?;
+        Ok((p.to_owned(), z, report.opstamp))
+    }
+
+    /// Builds the graph of the note `center` and its neighbors up to `depth` hops away, following
+    /// both the links of the notes and the ones pointing to them.
+    #[cfg(feature = "graph")]
+    pub fn subgraph(&self, mut center: Zest, depth: usize) -> Result<Subgraph, DatabaseError> {
+        let path = std::fs::canonicalize(&center).map_err(|e| DatabaseError::DirectoryError(e))?;
+        center.file = path.to_str().unwrap().to_owned();
+
+        let Neighborhood { reached, links } = self.bfs(&center.file, depth)?;
+        let mut nodes = vec![center.clone()];
+        let mut boundary = HashSet::new();
+        for (path, distance) in reached.into_iter().skip(1) {
+            if distance == depth {
+                boundary.insert(path.clone());
+            }
+            nodes.extend(read_indexed_note(path));
+        }
+
+        let find = |path: &str| nodes.iter().find(|n| n.file == path).cloned();
+        let edges = links
+            .iter()
+            .filter_map(|(source, target)| Some((find(source)?, find(target)?)))
+            .collect();
+
+        Ok(Subgraph {
+            center,
+            nodes,
+            edges,
+            boundary,
+        })
+    }
+
+    /// Returns the paths of the notes at most `max_depth` hops away from the note at `start`,
+    /// following both the links of the notes and the ones pointing to them, closest first
+    #[cfg(feature = "graph")]
+    pub fn bfs_neighbors(
+        &self,
+        start: &str,
+        max_depth: usize,
+    ) -> Result<Vec<String>, DatabaseError> {
+        let neighborhood = self.bfs(start, max_depth)?;
+        Ok(neighborhood
+            .reached
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    /// Walks the links around `start` breadth first, up to `max_depth` hops
+    #[cfg(feature = "graph")]
+    fn bfs(&self, start: &str, max_depth: usize) -> Result<Neighborhood, DatabaseError> {
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let query_parser = self.reader.query_parser(&schema);
+
+        let mut reached = vec![(start.to_owned(), 0)];
+        let mut seen: HashSet<String> = HashSet::new();
+        seen.insert(start.to_owned());
+        let mut links = Vec::new();
+        let mut next = 0;
+        while let Some((path, depth)) = reached.get(next).cloned() {
+            next += 1;
+            if depth >= max_depth {
+                continue;
+            }
+
+            let mut neighbors = Vec::new();
+            match Zest::from_file(path.clone()) {
+                Ok(z) => {
+                    let dir = z.path().parent().unwrap_or_else(|| Path::new(""));
+                    for reff in &z.refs {
+                        let dests = match resolve_ref(&searcher, &query_parser, &schema, dir, reff)
+                        {
+                            Ok(dests) => dests,
+                            Err(e) => {
+                                log::warn!("Could not resolve {} link {}: {}", path, reff, e);
+                                continue;
+                            }
+                        };
+                        if dests.is_empty() {
+                            log::warn!("{} contains a broken link: {}", path, reff);
+                        }
+                        for dest in dests {
+                            links.push((path.clone(), dest.clone()));
+                            neighbors.push(dest);
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Could not read the links of {}: {}", path, e),
+            }
+
+            let query = TermQuery::new(
+                Term::from_field_text(schema.link, &path),
+                IndexRecordOption::Basic,
+            );
+            for source in matching_paths(&searcher, &schema, &query, None)? {
+                links.push((source.clone(), path.clone()));
+                neighbors.push(source);
+            }
+
+            for neighbor in neighbors {
+                if seen.insert(neighbor.clone()) {
+                    reached.push((neighbor, depth + 1));
+                }
+            }
+        }
+
+        links.sort();
+        links.dedup();
+        Ok(Neighborhood { reached, links })
+    }
+
+    /// Builds the graph of the notes of the database, linked by their references
+    pub fn build_graph(&self) -> Result<NoteGraph, DatabaseError> {
+        let notes = self.all()?;
+
+        // Only lease the searcher now, `all` needs one too
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let query_parser = self.reader.query_parser(&schema);
+
+        let mut edges = Vec::with_capacity(notes.len());
+        for source in &notes {
+            let dir = source.path().parent().unwrap_or_else(|| Path::new(""));
+            for reff in &source.refs {
+                let dests = match resolve_ref(&searcher, &query_parser, &schema, dir, reff) {
+                    Ok(dests) => dests,
+                    Err(e) => {
+                        log::warn!("Could not resolve {} link {}: {}", source.file, reff, e);
+                        continue;
+                    }
+                };
+                match dests.len() {
+                    0 => log::warn!("{} contains a broken link: {}", source.file, reff),
+                    1 => {}
+                    _ => log::warn!(
+                        "{} contains a link that matches multiple files: {}",
+                        source.file,
+                        reff
+                    ),
+                }
+                edges.extend(dests.into_iter().map(|d| (source.file.clone(), d)));
+            }
+        }
+
+        Ok(NoteGraph {
+            nodes: notes.into_iter().map(|z| z.file).collect(),
+            edges,
+        })
+    }
+
+    /// Returns the link density of each note, that is its number of links to other notes per 100
+    /// words, by path
+    pub fn link_density_stats(&self) -> Result<HashMap<String, f64>, DatabaseError> {
+        let mut stats = HashMap::new();
+        self.reader
+            .for_each_matching(&DatabaseSchema::new(), &AllQuery, |z| {
+                let density = z.link_density();
+                stats.insert(z.file, density);
+            })?;
+        Ok(stats)
+    }
+
+    /// Renames the tag `old` to `new` in all the notes having it, both in their files and in the
+    /// index. Returns the number of modified notes: the ones that can't be rewritten, like org
+    /// notes, are skipped with a warning.
+    pub fn rename_tag(&mut self, old: &str, new: &str) -> Result<usize, DatabaseError> {
+        let schema = DatabaseSchema::new();
+        let query = TermQuery::new(
+            Term::from_field_text(schema.tag, old),
+            IndexRecordOption::Basic,
+        );
+        // Don't rewrite any file when the index can't be updated afterwards
+        self.writer()?;
+        let searcher = self.reader.searcher();
+        let paths = matching_paths(&searcher, &schema, &query, None)?;
+
+        // A note that can't be rewritten, like an org note, is left as is: the ones rewritten
+        // before it must still be committed
+        let mut renamed = 0;
+        for path in paths {
+            let mut z = match self.parse_file(path.clone()) {
+                Ok(z) => z,
+                Err(e) => {
+                    log::warn!("Could not rename tag {} in {}: {}", old, path, e);
+                    continue;
+                }
+            };
+            if !z.rename_tag(old, new) {
+                // The note changed since it was indexed, it will be updated on the next refresh
+                log::warn!("{} is not tagged {} anymore", path, old);
+                continue;
+            }
+            if let Err(e) = z.write_to_file() {
+                log::warn!("Could not rename tag {} in {}: {}", old, path, e);
+                continue;
+            }
+            log::info!("Renamed tag {} to {} in {}", old, new, path);
+
+            // Parse it again, so that the indexed note is the one of the written file
+            let indexed = self
+                .parse_file(path.clone())
+                .map_err(|e| DatabaseError::ParseError(path.clone(), e))
+                .and_then(|z| self.put_doc(z, &schema, &searcher));
+            match indexed {
+                Ok(_) => renamed += 1,
+                Err(e) => log::warn!("Could not index {} again: {}", path, e),
+            }
+        }
+        drop(searcher);
+
+        self.commit()?;
+        Ok(renamed)
+    }
+
+    /// Exports all the markdown notes to `output_dir` as an Obsidian vault: each note is written to
+    /// `<title>.md`, and its links to other notes are replaced by wikilinks. Returns the number
+    /// of exported notes.
+    pub fn export_obsidian(&self, output_dir: &Path) -> Result<usize, DatabaseError> {
+        std::fs::create_dir_all(output_dir).map_err(|e| DatabaseError::DirectoryError(e))?;
+
+        let mut notes = self.all()?;
+        notes.retain(|z| {
+            let markdown = FileFormat::from_path(&z.file) == FileFormat::Markdown;
+            if !markdown {
+                log::warn!("{} is not a markdown note, not exporting it", z.file);
+            }
+            markdown
+        });
+
+        // Obsidian identifies notes by their file name, so give each note a unique one
+        let mut names: HashMap<&str, String> = HashMap::with_capacity(notes.len());
+        let mut used: HashSet<String> = HashSet::with_capacity(notes.len());
+        for z in &notes {
+            let base = obsidian_name(z);
+            let mut name = base.clone();
+            let mut i = 2;
+            while !used.insert(name.to_lowercase()) {
+                name = format!("{} ({})", base, i);
+                i += 1;
+            }
+            names.insert(&z.file, name);
+        }
+
+        // Resolve the links as when indexing the notes, so that the same ones are converted
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let query_parser = self.reader.query_parser(&schema);
+        for z in &notes {
+            let dir = z.path().parent().unwrap_or_else(|| Path::new(""));
+            let exported = z
+                .to_obsidian(|dest| {
+                    // External links and anchors in the note itself are left untouched
+                    let (refs, _) = Zest::split_refs(vec![dest.to_owned()]);
+                    let reff = refs.first()?;
+                    match resolve_ref(&searcher, &query_parser, &schema, dir, reff) {
+                        Ok(matching) if matching.len() == 1 => {
+                            names.get(matching[0].as_str()).cloned()
+                        }
+                        _ => {
+                            log::warn!("{} link {} can't be converted", z.file, dest);
+                            None
+                        }
+                    }
+                })
+                .map_err(|e| DatabaseError::DirectoryError(e))?;
+
+            // Titles may contain dots, which are not extensions
+            let path = output_dir.join(format!("{}.md", names[z.file.as_str()]));
+            log::info!("Exporting {} to {}", z.file, path.display());
+            std::fs::write(path, exported).map_err(|e| DatabaseError::DirectoryError(e))?;
+        }
+
+        Ok(notes.len())
+    }
+
+    /// Rebuilds the whole index from the tracked notes. All of them are parsed before anything is
+    /// deleted, so that the index is left untouched when one of them can't be parsed. The notes
+    /// that do not exist anymore are dropped.
+    pub fn reindex(&mut self) -> Result<PutReport, DatabaseError> {
+        // Don't parse everything for nothing when the database is read-only
+        self.writer()?;
+
+        let start = Instant::now();
+        let schema = DatabaseSchema::new();
+        let searcher = self.reader.searcher();
+        let tracked = matching_paths(&searcher, &schema, &AllQuery, None)?;
+
+        let mut ops = Vec::with_capacity(tracked.len());
+        for fname in tracked {
+            let z = match self.parse_file(fname.clone()) {
+                Ok(z) => z,
+                Err(ZestParsingError::SourceError(e))
+                    if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::IsADirectory) =>
+                {
+                    log::info!("{} is not a note anymore, dropping it", fname);
+                    continue;
+                }
+                Err(e) => return Err(DatabaseError::ParseError(fname, e)),
+            };
+            let (_, doc) = self.note_document(z, &schema, &searcher)?;
+            ops.push(UserOperation::Add(doc));
+        }
+        drop(searcher);
+
+        let ops_count = ops.len();
+        let writer = self.writer()?;
+        writer
+            .delete_all_documents()
+            .map_err(|e| DatabaseError::PutError(e))?;
+        writer.run(ops);
+
+        let opstamp = self.commit()?;
+        log::debug!(
+            target: PROFILE_TARGET,
+            "Reindex in {}ms",
+            start.elapsed().as_millis()
+        );
+        // Every note is rebuilt from the tracked ones
+        Ok(PutReport {
+            opstamp,
+            updated: ops_count,
+            ..PutReport::default()
+        })
+    }
+}
+
+/// Restricts `query` to the notes modified since `since`, if set
+fn modified_since(
+    schema: &DatabaseSchema,
+    query: Box<dyn Query>,
+    since: Option<&DateTime>,
+) -> Box<dyn Query> {
+    match since {
+        Some(since) => {
+            let range = RangeQuery::new_term_bounds(
+                schema.last_modif,
+                Type::Date,
+                &Bound::Included(Term::from_field_date(schema.last_modif, since)),
+                &Bound::Unbounded,
+            );
+            Box::new(BooleanQuery::intersection(vec![query, Box::new(range)]))
+        }
+        None => query,
+    }
+}
+
+fn parse_query(query_parser: &QueryParser, query: &str) -> Result<Box<dyn Query>, DatabaseError> {
+    let query = normalize_query(query);
+    query_parser
+        .parse_query(&query)
+        .map_err(|e| DatabaseError::QueryError(query, e))
+}
+
+/// Trims `query` and collapses the whitespace between its terms, which the query parser does not
+/// always handle the same way
+fn normalize_query(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Returns the number of notes linking to the note at `path`
+fn backlink_count(
+    searcher: &Searcher,
+    schema: &DatabaseSchema,
+    path: &str,
+) -> Result<usize, DatabaseError> {
+    let query = TermQuery::new(
+        Term::from_field_text(schema.link, path),
+        IndexRecordOption::Basic,
+    );
+    searcher
+        .search(&query, &Count)
+        .map_err(|e| DatabaseError::ReadError(e))
+}
+
+/// Returns the documents matching `query`. When `limit` is set, only the `limit` best matching
+/// documents are returned, so that broad queries on big databases don't collect everything.
+fn matching_docs(
+    searcher: &Searcher,
+    query: &dyn Query,
+    limit: Option<usize>,
+) -> Result<Vec<DocAddress>, DatabaseError> {
+    let docs = match limit {
+        Some(0) => Vec::new(),
+        Some(limit) => {
+            let (top_docs, count) = searcher
+                .search(query, &(TopDocs::with_limit(limit), Count))
+                .map_err(|e| DatabaseError::ReadError(e))?;
+            if count > limit {
+                log::warn!(
+                    "{} notes match the query, only keeping the first {}",
+                    count,
+                    limit
+                );
+            }
+            top_docs.into_iter().map(|(_, doc)| doc).collect()
+        }
+        None => searcher
+            .search(query, &DocSetCollector)
+            .map_err(|e| DatabaseError::ReadError(e))?
+            .into_iter()
+            .collect(),
+    };
+    Ok(docs)
+}
+
+/// Fetches the stored fields of the document at `address`. Its segment may have been removed by a
+/// concurrent update since it was found, it is then skipped as if it did not match.
+fn fetch_doc(searcher: &Searcher, address: DocAddress) -> Option<Document> {
+    match searcher.doc(address) {
+        Ok(doc) => Some(doc),
+        Err(e) => {
+            log::warn!("Could not read the matching document {:?}: {}", address, e);
+            None
+        }
+    }
+}
+
+/// Returns the paths of the notes matching `query`, sorted
+fn matching_paths(
+    searcher: &Searcher,
+    schema: &DatabaseSchema,
+    query: &dyn Query,
+    limit: Option<usize>,
+) -> Result<Vec<String>, DatabaseError> {
+    let docs = matching_docs(searcher, query, limit)?;
+
+    let mut returned: Vec<String> = Vec::with_capacity(docs.len());
+    for doc_address in docs {
+        let doc = match fetch_doc(searcher, doc_address) {
+            Some(doc) => doc,
+            None => continue,
+        };
+        let fname = doc
+            .get_first(schema.path)
+            .ok_or(DatabaseError::CorruptionError("missing path field"))?
+            .text()
+            .ok_or(DatabaseError::CorruptionError("wrong type for path field"))?
+            .to_string();
+        returned.push(fname);
+    }
+
+    // Sort by path so that the results are the same as the ones of `search`
+    returned.sort();
+    Ok(returned)
+}
+
+/// Returns the paths of the notes a link of a note in `dir` points to
+fn resolve_ref(
+    searcher: &Searcher,
+    query_parser: &QueryParser,
+    schema: &DatabaseSchema,
+    dir: &Path,
+    reff: &str,
+) -> Result<Vec<String>, DatabaseError> {
+    // Most links are paths relative to the note, which need no lookup in the index
+    let target = std::fs::canonicalize(dir.join(reff))
+        .ok()
+        .filter(|target| target.is_file())
+        .and_then(|target| target.to_str().map(String::from));
+    if let Some(target) = target {
+        return Ok(vec![target]);
+    }
+
+    let q = parse_query(query_parser, &format!("file:{}", reff))?;
+    matching_paths(searcher, schema, &q, None)
+}
+
+/// The name of a note in an Obsidian vault, that is its title without the characters Obsidian
+/// forbids in file names
+fn obsidian_name(z: &Zest) -> String {
+    let name: String = z
+        .title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | '^' | '[' | ']' => ' ',
+            c => c,
+        })
+        .collect();
+    let name = name.trim();
+
+    if name.is_empty() {
+        z.path()
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("Untitled")
+            .to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+#[cfg(feature = "graph")]
+fn graph_node_id<'a>(n: &Zest) -> dot::Id<'a> {
+    let meta = std::fs::metadata(n.file.clone()).unwrap();
+    let mod_time = DateTime::from(meta.modified().unwrap());
+    dot::Id::new(mod_time.format("N%Y%m%d%H%M%S").to_string()).unwrap()
+}
+
+#[cfg(feature = "graph")]
+impl<'a> Labeller<'a, Zest, (Zest, Zest)> for Database {
+    fn graph_id(&'a self) -> dot::Id<'a> {
+        dot::Id::new("database").unwrap()
+    }
+
+    fn node_id(&'a self, n: &Zest) -> dot::Id<'a> {
+        graph_node_id(n)
+    }
+
+    fn node_label(&'a self, n: &Zest) -> dot::LabelText<'a> {
+        dot::LabelText::label(n.title.clone())
+    }
+}
+
+#[cfg(feature = "graph")]
+impl<'a> GraphWalk<'a, Zest, (Zest, Zest)> for Database {
+    fn nodes(&'a self) -> dot::Nodes<'a, Zest> {
+        Cow::Owned(self.all().unwrap())
+    }
+
+    fn edges(&'a self) -> dot::Edges<'a, (Zest, Zest)> {
+        let graph = self.build_graph().unwrap();
+        let by_path: HashMap<&str, Zest> = graph
+            .nodes
+            .iter()
+            .filter_map(|p| Some((p.as_str(), Zest::from_file(p.clone()).ok()?)))
+            .collect();
+
+        let edges = graph
+            .edges
+            .iter()
+            .filter_map(|(source, target)| {
+                Some((
+                    by_path.get(source.as_str())?.clone(),
+                    by_path.get(target.as_str())?.clone(),
+                ))
+            })
+            .collect();
+
+        Cow::Owned(edges)
+    }
+
+    fn source(&'a self, edge: &(Zest, Zest)) -> Zest {
+        edge.0.clone()
+    }
+
+    fn target(&'a self, edge: &(Zest, Zest)) -> Zest {
+        edge.1.clone()
+    }
+}
+
+/// The notes of the database, identified by their path, and the links between them, as built by
+/// `Database::build_graph`
+#[derive(Debug, Default, Clone)]
+pub struct NoteGraph {
+    pub nodes: Vec<String>,
+    /// The `(source, target)` links
+    pub edges: Vec<(String, String)>,
+}
+
+/// The notes around a given one, as walked by `Database::bfs`
+#[cfg(feature = "graph")]
+struct Neighborhood {
+    /// The paths of the notes reached along with their distance to the first one, closest first
+    reached: Vec<(String, usize)>,
+    /// The `(source, target)` links followed
+    links: Vec<(String, String)>,
+}
+
+/// The neighborhood of a single note, as built by `Database::subgraph`
+#[cfg(feature = "graph")]
+pub struct Subgraph {
+    center: Zest,
+    nodes: Vec<Zest>,
+    edges: Vec<(Zest, Zest)>,
+    /// Paths of the notes at the maximum depth, whose own neighbors are not shown
+    boundary: HashSet<String>,
+}
+
+#[cfg(feature = "graph")]
+impl<'a> Labeller<'a, Zest, (Zest, Zest)> for Subgraph {
+    fn graph_id(&'a self) -> dot::Id<'a> {
+        dot::Id::new("subgraph").unwrap()
+    }
+
+    fn node_id(&'a self, n: &Zest) -> dot::Id<'a> {
+        graph_node_id(n)
+    }
+
+    fn node_label(&'a self, n: &Zest) -> dot::LabelText<'a> {
+        if n.file == self.center.file {
+            dot::LabelText::html(format!(
+                "<FONT POINT-SIZE=\"20\"><B>{}</B></FONT>",
+                dot::escape_html(&n.title)
+            ))
+        } else {
+            dot::LabelText::label(n.title.clone())
+        }
+    }
+
+    fn node_style(&'a self, n: &Zest) -> dot::Style {
+        if n.file == self.center.file {
+            dot::Style::Bold
+        } else if self.boundary.contains(&n.file) {
+            dot::Style::Dashed
+        } else {
+            dot::Style::None
+        }
+    }
+}
+
+#[cfg(feature = "graph")]
+impl<'a> GraphWalk<'a, Zest, (Zest, Zest)> for Subgraph {
+    fn nodes(&'a self) -> dot::Nodes<'a, Zest> {
+        Cow::Borrowed(&self.nodes)
+    }
+
+    fn edges(&'a self) -> dot::Edges<'a, (Zest, Zest)> {
+        Cow::Borrowed(&self.edges)
+    }
+
+    fn source(&'a self, edge: &(Zest, Zest)) -> Zest {
+        edge.0.clone()
+    }
+
+    fn target(&'a self, edge: &(Zest, Zest)) -> Zest {
+        edge.1.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    /// Creates a database in a temporary directory, tracking the notes `(name, content)`
+    fn database_with(notes: &[(&str, &str)]) -> (TempDir, Database) {
+        let dir = TempDir::new().unwrap();
+        let notes_dir = dir.path().join("notes");
+        std::fs::create_dir(&notes_dir).unwrap();
+        for (name, content) in notes {
+            let mut file = File::create(notes_dir.join(name)).unwrap();
+            file.write_all(content.as_bytes()).unwrap();
+        }
+
+        let config = dir.path().join("config.yml");
+        std::fs::write(&config, format!("paths:\n  - {}\n", notes_dir.display())).unwrap();
+
+        let mut db =
+            Database::open_or_create_at(Some(&dir.path().join("index")), Some(&config), false)
+                .unwrap();
+        db.update().unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn unknown_field_hint() {
+        let (_dir, db) = database_with(&[]);
+        let hint = db
+            .list(String::from("sectoin:foo"))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            hint,
+            "Unknown field 'sectoin'. Valid fields are: title, content, tag, file, ref, lastmod, \
+             created, code, section."
+        );
+    }
+
+    #[test]
+    fn export_obsidian() {
+        let (dir, db) = database_with(&[
+            (
+                "release12.md",
+                "# Release 1.2\n\nSee [next](release13.md#changes)",
+            ),
+            (
+                "release13.md",
+                "# Release 1.3\n\nSee [the notes](<my notes.md>)",
+            ),
+            (
+                "my notes.md",
+                "# My notes\n\nSee [the site](https://example.com)",
+            ),
+        ]);
+        let vault = dir.path().join("vault");
+
+        assert_eq!(db.export_obsidian(&vault).unwrap(), 3);
+        let read = |name: &str| std::fs::read_to_string(vault.join(name)).unwrap();
+        assert_eq!(
+            read("Release 1.2.md"),
+            "# Release 1.2\n\nSee [[Release 1.3|next]]"
+        );
+        assert_eq!(
+            read("Release 1.3.md"),
+            "# Release 1.3\n\nSee [[My notes|the notes]]"
+        );
+        assert_eq!(
+            read("My notes.md"),
+            "# My notes\n\nSee [the site](https://example.com)"
+        );
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let (_dir, db) = database_with(&[("note.md", "# Note\n\nSome content")]);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = db.reader();
+                std::thread::spawn(move || reader.search(String::from("content")).unwrap())
+            })
+            .collect();
+
+        for handle in handles {
+            let found = handle.join().unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].title, "Note");
+        }
+    }
+
+    #[test]
+    fn search_phrase() {
+        let (_dir, db) = database_with(&[
+            ("exact.md", "# Exact\n\nSay hello world to everyone"),
+            ("split.md", "# Split\n\nSay hello to the whole world"),
+        ]);
+
+        let found = db.search(String::from("\"hello world\"")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].title, "Exact");
+
+        let found = db.search(String::from("hello world")).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(db.count_results(String::from("hello world")).unwrap(), 2);
+    }
+
+    #[test]
+    fn reindex_keeps_index_on_parse_error() {
+        let (dir, mut db) = database_with(&[
+            ("good.md", "# Good\n\nContent"),
+            ("bad.md", "# Bad\n\nContent"),
+        ]);
+
+        std::fs::write(dir.path().join("notes/bad.md"), "---\ntags: [\n---\n# Bad").unwrap();
+        assert_matches!(db.reindex(), Err(DatabaseError::ParseError(..)));
+        assert_eq!(db.list_all().unwrap().len(), 2);
+
+        std::fs::remove_file(dir.path().join("notes/bad.md")).unwrap();
+        db.reindex().unwrap();
+        let found = db.all().unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].title, "Good");
+    }
+
+    #[test]
+    fn note_graph() {
+        let (dir, db) = database_with(&[
+            // The last link is not even a valid file query, it is skipped like a broken one
+            (
+                "a.md",
+                "# A\n\nSee [b](b.md), [nothing](missing.md) and [odd](<(odd>)",
+            ),
+            ("b.md", "# B\n\nBack to [a](a.md)"),
+            ("c.md", "# C"),
+        ]);
+        let path = |name: &str| {
+            let p = std::fs::canonicalize(dir.path().join("notes").join(name)).unwrap();
+            p.to_str().unwrap().to_owned()
+        };
+
+        assert!(db.list(String::from("file:(odd")).is_err());
+        let graph = db.build_graph().unwrap();
+        assert_eq!(graph.nodes, [path("a.md"), path("b.md"), path("c.md")]);
+        assert_eq!(
+            graph.edges,
+            [(path("a.md"), path("b.md")), (path("b.md"), path("a.md"))]
+        );
+    }
+
+    #[test]
+    fn up_to_date() {
+        let (dir, db) = database_with(&[("note.md", "# Note")]);
+        let note = dir.path().join("notes/note.md");
+        let note = note.to_str().unwrap();
+
+        assert!(db.is_up_to_date(note).unwrap());
+        std::fs::write(note, "# Changed note").unwrap();
+        assert!(!db.is_up_to_date(note).unwrap());
+
+        let untracked = dir.path().join("untracked.md");
+        std::fs::write(&untracked, "# Untracked").unwrap();
+        assert!(!db.is_up_to_date(untracked.to_str().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn tags_prefix() {
+        let (_dir, db) = database_with(&[
+            ("a.md", "---\ntags: [rust, rustdoc]\n---\n# A"),
+            ("b.md", "---\ntags: [rust, python]\n---\n# B"),
+        ]);
+
+        assert_eq!(
+            db.tags_starting_with("rust").unwrap(),
+            [(String::from("rust"), 2), (String::from("rustdoc"), 1)]
+        );
+        assert_eq!(db.tags_starting_with("").unwrap().len(), 3);
+        assert!(db.tags_starting_with("go").unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_aliases() {
+        let (_dir, db) = database_with(&[(
+            "note.md",
+            "---\naliases:\n  - Alternative Title\n---\n# Note\n\nContent",
+        )]);
+
+        let found = db.search(String::from("title:alternative")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].title, "Note");
+    }
+
+    #[test]
+    fn search_alt_titles() {
+        let (_dir, db) = database_with(&[(
+            "note.md",
+            "---\ntitle: Metadata Title\n---\n# Heading Title\n\nContent",
+        )]);
+
+        for query in &["title:metadata", "title:heading"] {
+            let found = db.search(query.to_string()).unwrap();
+            assert_eq!(found.len(), 1, "{}", query);
+            assert_eq!(found[0].title, "Metadata Title");
+        }
+    }
+
+    #[test]
+    fn search_file_name() {
+        let (_dir, db) = database_with(&[("my-note.md", "# Note"), ("other.md", "# Other")]);
+
+        let found = db.list(String::from("file:my-note")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].ends_with("my-note.md"));
+        assert_eq!(db.list(String::from("file:other")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn create_with_metadata() {
+        let (_dir, mut db) = database_with(&[]);
+        let meta = ZestMeta {
+            tags: vec![String::from("idea")],
+            description: Some(String::from("Something to try")),
+            ..ZestMeta::default()
+        };
+
+        let (path, z, _) = db.create(Some("New note"), &meta).unwrap();
+        assert_eq!(z.title, "New note");
+        assert_eq!(z.tags(), ["idea"]);
+        assert_eq!(z.description(), Some("Something to try"));
+        assert_eq!(db.list(String::from("tag:idea")).unwrap(), [path]);
+
+        // Notes are named after their creation time, use another database to create one more
+        let (_dir, mut db) = database_with(&[]);
+        let (_, z, _) = db.create(None, &ZestMeta::default()).unwrap();
+        assert_eq!(z.title, "Untitled");
+        assert!(z.tags().is_empty());
+    }
+
+    #[test]
+    fn search_markup_remnants() {
+        let (_dir, db) = database_with(&[(
+            "note.md",
+            "# Note\n\nSome **unclosed bold, __half__ emphasis_ and ~~strike~~ text",
+        )]);
+
+        for query in &[
+            "content:unclosed",
+            "content:\"unclosed bold\"",
+            "content:\"half emphasis\"",
+            "content:\"strike text\"",
+        ] {
+            assert_eq!(db.count_results(query.to_string()).unwrap(), 1, "{}", query);
+        }
+    }
+
+    #[test]
+    fn search_since() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = TempDir::new().unwrap();
+        let notes = dir.path().join("notes");
+        std::fs::create_dir(&notes).unwrap();
+        std::fs::write(notes.join("recent.md"), "# Recent rust").unwrap();
+        let old = File::create(notes.join("old.md")).unwrap();
+        (&old).write_all(b"# Old rust").unwrap();
+        let month = Duration::from_secs(30 * 24 * 60 * 60);
+        old.set_modified(SystemTime::now() - month).unwrap();
+
+        let config = dir.path().join("config.yml");
+        std::fs::write(&config, format!("paths:\n  - {}\n", notes.display())).unwrap();
+        let mut db =
+            Database::open_or_create_at(Some(&dir.path().join("index")), Some(&config), false)
+                .unwrap();
+        db.update().unwrap();
+
+        let week_ago = (SystemTime::now() - month / 4).into();
+        let found = db
+            .reader()
+            .search_since(String::from("rust"), &week_ago)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].title, "Recent rust");
+        let found = db
+            .reader()
+            .list_since(String::from("rust"), &week_ago)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(db.list(String::from("rust")).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn search_code() {
+        let (_dir, db) = database_with(&[
+            ("inline.md", "# Inline\n\nUse a `HashMap` here."),
+            (
+                "block.md",
+                "# Block\n\n```rust\nlet m = HashMap::new();\n```",
+            ),
+            ("prose.md", "# Prose\n\nA HashMap is a map."),
+        ]);
+
+        let found = db.list(String::from("code:hashmap")).unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|f| !f.ends_with("prose.md")));
+    }
+
+    #[test]
+    fn query_options() {
+        let (_dir, db) = database_with(&[
+            ("a.md", "# A\n\nrust"),
+            ("b.md", "# B\n\nrust rust rust"),
+            ("c.md", "# C\n\nrust rust"),
+            ("d.md", "# D\n\npython"),
+        ]);
+
+        let found = db.query("rust", SearchOptions::default()).unwrap();
+        assert_eq!(found.total, 3);
+        let titles: Vec<String> = found.notes().into_iter().map(|z| z.title).collect();
+        assert_eq!(titles, ["A", "B", "C"]);
+
+        let opts = SearchOptions::default()
+            .only_paths(true)
+            .score(true)
+            .limit(2);
+        let found = db.query("rust", opts.clone()).unwrap();
+        assert_eq!(found.total, 3);
+        assert!(found.hits.iter().all(|h| h.note.is_none()));
+        assert!(found.hits[0].score.unwrap() > found.hits[1].score.unwrap());
+        let paths = found.paths();
+        assert!(paths[0].ends_with("b.md") && paths[1].ends_with("c.md"));
+
+        let next = db.query("rust", opts.offset(2)).unwrap().paths();
+        assert_eq!(next.len(), 1);
+        assert!(next[0].ends_with("a.md"));
+
+        let none = db.query("rust", SearchOptions::default().limit(0)).unwrap();
+        assert!(none.hits.is_empty());
+        assert_eq!(none.total, 3);
+    }
+
+    #[test]
+    fn query_boost() {
+        let (_dir, db) = database_with(&[
+            ("title.md", "# Rust\n\nA language."),
+            ("content.md", "# Languages\n\nrust"),
+        ]);
+
+        let first = |opts: SearchOptions| {
+            let found = db.query("rust", opts.score(true)).unwrap();
+            found.hits[0].path.clone()
+        };
+        assert!(first(SearchOptions::default().boost("title", 10.0)).ends_with("title.md"));
+        assert!(first(SearchOptions::default().boost("content", 10.0)).ends_with("content.md"));
+        assert_matches!(
+            db.query("rust", SearchOptions::default().boost("nope", 2.0)),
+            Err(DatabaseError::QueryError(
+                _,
+                QueryParserError::FieldDoesNotExist(_)
+            ))
+        );
+    }
+
+    #[test]
+    fn search_list_items() {
+        let (_dir, db) = database_with(&[("langs.md", "# Languages\n\n- Python\n- Rust\n- Go\n")]);
+        for word in &["python", "rust", "go"] {
+            assert_eq!(db.list(word.to_string()).unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn for_each() {
+        let (_dir, db) = database_with(&[
+            ("b.md", "# B\n\nrust"),
+            ("a.md", "# A\n\nrust"),
+            ("c.md", "# C\n\npython"),
+        ]);
+
+        let mut titles = Vec::new();
+        db.for_each("rust", |z| titles.push(z.title)).unwrap();
+        assert_eq!(titles, ["A", "B"]);
+        assert_matches!(
+            db.for_each("title:(", |_| {}),
+            Err(DatabaseError::QueryError(..))
+        );
+    }
+
+    #[test]
+    fn search_fast() {
+        let (_dir, db) = database_with(&[
+            (
+                "a.md",
+                "---\ntitle: Meta title\naliases: [Alias]\n---\n# Heading\n\nShared",
+            ),
+            ("b.md", "# B\n\nShared"),
+        ]);
+
+        let found = db.search_fast(String::from("shared")).unwrap();
+        let titles: Vec<&str> = found.iter().map(|(_, t)| t.as_str()).collect();
+        assert_eq!(titles, ["Meta title", "B"]);
+        assert!(found[0].0.ends_with("a.md"));
+    }
+
+    #[test]
+    fn search_default_fields() {
+        let (_dir, db) = database_with(&[
+            (
+                "tagged.md",
+                "---\ntags: [gardening]\n---\n# Tagged\n\nAbout plants.",
+            ),
+            ("section.md", "# Section\n\n## Gardening\n\nAbout plants."),
+            ("other.md", "# Other\n\nAbout plants."),
+        ]);
+
+        let found = db.list(String::from("gardening")).unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|f| !f.ends_with("other.md")));
+        assert_eq!(db.list(String::from("section:gardening")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn whitespace_query() {
+        let (_dir, db) = database_with(&[("a.md", "# A\n\nAbout plants and trees.")]);
+
+        assert_eq!(
+            normalize_query("  plants \t AND\n trees "),
+            "plants AND trees"
+        );
+        assert_eq!(normalize_query("   "), "");
+        assert!(db.list(String::from("   ")).unwrap().is_empty());
+        assert_eq!(
+            db.list(String::from("  plants   AND  trees ")).unwrap(),
+            db.list(String::from("plants AND trees")).unwrap()
+        );
+        assert_eq!(db.list(String::from("\tplants\n")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn link_to_unindexed_note() {
+        let (dir, mut db) = database_with(&[]);
+        let a = dir.path().join("notes/a.md");
+        let b = dir.path().join("notes/b.md");
+        std::fs::write(&a, "# A\n\nSee [b](b.md#part)").unwrap();
+        std::fs::write(&b, "# B").unwrap();
+
+        // The link is resolved from the file system, even though b is not indexed yet
+        db.put(Zest::from_file(a.to_str().unwrap().to_owned()).unwrap())
+            .unwrap();
+        let b = std::fs::canonicalize(b).unwrap();
+        let linking = db
+            .list(format!("{}:\"{}\"", REF_FIELD, b.to_str().unwrap()))
+            .unwrap();
+        assert_eq!(linking.len(), 1);
+        assert!(linking[0].ends_with("a.md"));
+    }
+
+    /// The read operations only need a shared reference to the database
+    fn read_links(db: &Database, path: &str) -> (Vec<String>, Vec<Zest>) {
+        let linking = db.list(format!("{}:\"{}\"", REF_FIELD, path)).unwrap();
+        (linking, db.all().unwrap())
+    }
+
+    #[test]
+    fn shared_reads() {
+        let (dir, mut db) = database_with(&[("a.md", "# A\n\nSee [b](b.md)"), ("b.md", "# B")]);
+        // Resolve the links now that both notes are indexed
+        db.reindex().unwrap();
+        let b = std::fs::canonicalize(dir.path().join("notes/b.md")).unwrap();
+        let b = b.to_str().unwrap();
+
+        let (linking, all) = read_links(&db, b);
+        assert_eq!(linking.len(), 1);
+        assert!(linking[0].ends_with("a.md"));
+        assert_eq!(all.len(), 2);
+
+        #[cfg(feature = "graph")]
+        {
+            let backlinks = db.find_backlinks(b).unwrap();
+            assert_eq!(backlinks.len(), 1);
+            assert_eq!(backlinks[0].title, "A");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "graph")]
+    fn bfs_neighbors() {
+        // e -> a -> b -> c -> d
+        let (dir, db) = database_with(&[
+            ("a.md", "# A\n\n[b](b.md) [odd](<(odd>)"),
+            ("b.md", "# B\n\n[c](c.md)"),
+            ("c.md", "# C\n\n[d](d.md)"),
+            ("d.md", "# D"),
+            ("e.md", "# E\n\n[a](a.md)"),
+        ]);
+        let path = |name: &str| {
+            let path = std::fs::canonicalize(dir.path().join("notes").join(name)).unwrap();
+            path.to_str().unwrap().to_owned()
+        };
+
+        assert_eq!(db.bfs_neighbors(&path("a.md"), 0).unwrap(), [path("a.md")]);
+        let mut found = db.bfs_neighbors(&path("a.md"), 2).unwrap();
+        assert_eq!(found.remove(0), path("a.md"));
+        found[..2].sort();
+        assert_eq!(found, [path("b.md"), path("e.md"), path("c.md")]);
+
+        let a = Zest::from_file(path("a.md")).unwrap();
+        let subgraph = db.subgraph(a, 2).unwrap();
+        assert_eq!(subgraph.nodes.len(), 4);
+        assert_eq!(subgraph.edges.len(), 3);
+        assert_eq!(subgraph.boundary, HashSet::from([path("c.md")]));
+    }
+
+    #[test]
+    fn schema_mismatch() {
+        let dir = TempDir::new().unwrap();
+        let index_dir = dir.path().join("index");
+        std::fs::create_dir(&index_dir).unwrap();
+        let mut old_schema = Schema::builder();
+        old_schema.add_text_field(TITLE_FIELD, TEXT);
+        Index::create_in_dir(&index_dir, old_schema.build()).unwrap();
+
+        let config = dir.path().join("config.yml");
+        std::fs::write(&config, "paths: []\n").unwrap();
+        assert_matches!(
+            Database::open_or_create_at(Some(&index_dir), Some(&config), false).err(),
+            Some(DatabaseError::SchemaMismatch)
+        );
+
+        Database::recreate_at(Some(&index_dir), Some(&config)).unwrap();
+        assert!(Database::open_or_create_at(Some(&index_dir), Some(&config), true).is_ok());
+    }
+
+    #[test]
+    fn corrupt_index() {
+        let (dir, db) = database_with(&[("a.md", "# A"), ("b.md", "# B")]);
+        drop(db);
+        let index_dir = dir.path().join("index");
+        let config = dir.path().join("config.yml");
+
+        // Delete the segment files
+        for entry in std::fs::read_dir(&index_dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "idx") {
+                std::fs::remove_file(path).unwrap();
+            }
+        }
+        assert_matches!(
+            Database::open_or_create_at(Some(&index_dir), Some(&config), false).err(),
+            Some(DatabaseError::CorruptIndex(_))
+        );
+
+        std::fs::write(index_dir.join("meta.json"), "{ garbage").unwrap();
+        assert_matches!(
+            Database::open_or_create_at(Some(&index_dir), Some(&config), true).err(),
+            Some(DatabaseError::CorruptIndex(_))
+        );
+
+        let mut db = Database::recreate_at(Some(&index_dir), Some(&config)).unwrap();
+        db.update().unwrap();
+        assert_eq!(db.list_all().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn tantivy_version_mismatch() {
+        let (dir, db) = database_with(&[("a.md", "# A")]);
+        drop(db);
+        let index_dir = dir.path().join("index");
+        let config = dir.path().join("config.yml");
+
+        let index = Index::open_in_dir(&index_dir).unwrap();
+        let mut writer: IndexWriter = index.writer(WRITER_BUDGET).unwrap();
+        let mut prepared = writer.prepare_commit().unwrap();
+        prepared.set_payload("tantivy v0.1.2, index_format v1");
+        prepared.commit().unwrap();
+        drop(writer);
+
+        let current = tantivy_minor_version(tantivy::version_string()).to_owned();
+        assert_matches!(
+            Database::open_or_create_at(Some(&index_dir), Some(&config), true).err(),
+            Some(DatabaseError::VersionMismatch { index, current: c }) if index == "0.1" && c == current
+        );
+
+        let mut db = Database::recreate_at(Some(&index_dir), Some(&config)).unwrap();
+        db.update().unwrap();
+        drop(db);
+        assert!(Database::open_or_create_at(Some(&index_dir), Some(&config), true).is_ok());
+    }
+
+    #[test]
+    fn backlink_count() {
+        let (_dir, db) = database_with(&[
+            ("a.md", "# A\n\nSee [c](c.md)"),
+            ("b.md", "# B\n\nSee [c](c.md) and [a](a.md)"),
+            ("c.md", "# C"),
+        ]);
+
+        let counts: Vec<(String, usize)> = db
+            .search(String::from("*"))
+            .unwrap()
+            .into_iter()
+            .map(|z| (z.title, z.backlink_count))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                (String::from("A"), 1),
+                (String::from("B"), 0),
+                (String::from("C"), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn close() {
+        let (dir, db) = database_with(&[("a.md", "# A")]);
+        let index_dir = dir.path().join("index");
+        let config = dir.path().join("config.yml");
+        let reader = db.reader();
+
+        db.close().unwrap();
+        assert_eq!(reader.list_all().unwrap().len(), 1);
+        // The lock was released
+        assert!(Database::open_or_create_at(Some(&index_dir), Some(&config), false).is_ok());
+    }
+
+    #[test]
+    fn locked_index() {
+        let (dir, _db) = database_with(&[("a.md", "# A")]);
+        let index_dir = dir.path().join("index");
+        let config = dir.path().join("config.yml");
+
+        assert_matches!(
+            Database::open_or_create_at(Some(&index_dir), Some(&config), false).err(),
+            Some(DatabaseError::LockError(_))
+        );
+        // Reading does not need the lock
+        assert!(Database::open_or_create_at(Some(&index_dir), Some(&config), true).is_ok());
+    }
+
+    #[test]
+    fn writer_budget_bounds() {
+        let budget = writer_budget();
+        assert!((WRITER_BUDGET_MIN..=WRITER_BUDGET).contains(&budget));
+    }
+
+    #[test]
+    fn note_file_names() {
+        use tantivy::chrono::{TimeZone, Utc};
+
+        let time = Utc.ymd(2024, 3, 15).and_hms(14, 30, 22);
+        assert_eq!(
+            note_file_name(DEFAULT_FILENAME_TEMPLATE, &time).unwrap(),
+            "2024_03_15_14_30_22.md"
+        );
+        assert_eq!(note_file_name("%Y-%m-%d", &time).unwrap(), "2024-03-15.md");
+        assert_eq!(
+            note_file_name("zettel-%Y%m%d%H.markdown", &time).unwrap(),
+            "zettel-2024031514.markdown"
+        );
+        assert_eq!(
+            note_file_name("zettel-%Y%m%d%H.txt", &time).unwrap(),
+            "zettel-2024031514.txt.md"
+        );
+        assert_eq!(note_file_name("%Y.%m.%d", &time).unwrap(), "2024.03.15.md");
+        assert_eq!(note_file_name("%Y.org", &time).unwrap(), "2024.org.md");
+
+        let random = note_file_name("{random}", &time).unwrap();
+        assert_eq!(random.len(), "0123456789abcdef.md".len());
+        assert_ne!(random, note_file_name("{random}", &time).unwrap());
+        assert_matches!(
+            note_file_name("%Q", &time),
+            Err(DatabaseError::ConfigError(..))
+        );
+    }
+
+    #[test]
+    fn newer_at_second_precision() {
+        use tantivy::chrono::{TimeZone, Utc};
+
+        let stored = Utc.timestamp(1_700_000_000, 0);
+        assert!(!is_newer(&stored, &stored));
+        assert!(!is_newer(
+            &stored,
+            &Utc.timestamp(1_700_000_000, 500_000_000)
+        ));
+        assert!(!is_newer(&stored, &Utc.timestamp(1_699_999_999, 0)));
+        assert!(is_newer(&stored, &Utc.timestamp(1_700_000_001, 0)));
+    }
+
+    #[test]
+    fn opstamp_increases() {
+        let (dir, mut db) = database_with(&[("note.md", "# Note")]);
+        let before = db.current_opstamp().unwrap();
+
+        std::fs::write(dir.path().join("notes/other.md"), "# Other").unwrap();
+        db.update().unwrap();
+        assert!(db.current_opstamp().unwrap() > before);
+    }
+
+    #[test]
+    fn update_report() {
+        let (dir, mut db) = database_with(&[("kept.md", "# Kept"), ("deleted.md", "# Deleted")]);
+        let notes = dir.path().join("notes");
+
+        std::fs::write(notes.join("new.md"), "# New").unwrap();
+        std::fs::write(notes.join("kept.md"), "# Kept\n\nChanged").unwrap();
+        std::fs::remove_file(notes.join("deleted.md")).unwrap();
+
+        let report = db.update().unwrap();
+        assert_eq!((report.added, report.updated, report.removed), (1, 1, 1));
+        let report = db.update().unwrap();
+        assert_eq!((report.added, report.updated, report.removed), (0, 0, 0));
+    }
+
+    #[test]
+    fn file_replaced_by_directory() {
+        let (dir, mut db) = database_with(&[("a.md", "# A"), ("b.md", "# B")]);
+        let a = dir.path().join("notes/a.md");
+        std::fs::remove_file(&a).unwrap();
+        std::fs::create_dir(&a).unwrap();
+
+        assert_eq!(db.all().unwrap().len(), 1);
+        let report = db.refresh().unwrap();
+        assert_eq!((report.updated, report.removed), (0, 1));
+        assert_eq!(db.list_all().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rename_tag() {
+        let (dir, mut db) = database_with(&[
+            (
+                "a.md",
+                "---\ntags: [foo, bar]\nauthor: me\n---\n# A\n\nText of A",
+            ),
+            ("b.md", "---\ntags: [foo, qux]\n---\n# B"),
+            ("c.md", "---\ntags: [baz]\n---\n# C"),
+            ("d.org", "#+TITLE: D\n#+TAGS: foo\n"),
+        ]);
+
+        // Org notes can't be rewritten, they keep the old tag
+        assert_eq!(db.rename_tag("foo", "qux").unwrap(), 2);
+        let org = db.list(String::from("tag:foo")).unwrap();
+        assert_eq!(org.len(), 1);
+        assert!(org[0].ends_with("d.org"));
+        assert_eq!(db.list(String::from("tag:qux")).unwrap().len(), 2);
+
+        let a =
+            Zest::from_file(dir.path().join("notes/a.md").to_str().unwrap().to_owned()).unwrap();
+        assert_eq!(a.tags(), ["qux", "bar"]);
+        assert_eq!(a.content, "Text of A");
+        let raw = std::fs::read_to_string(dir.path().join("notes/a.md")).unwrap();
+        assert!(raw.contains("author: me"));
+        let b =
+            Zest::from_file(dir.path().join("notes/b.md").to_str().unwrap().to_owned()).unwrap();
+        assert_eq!(b.tags(), ["qux"]);
+        assert!(db.is_up_to_date(&b.file).unwrap());
+    }
+
+    #[test]
+    fn put_deleted_file() {
+        let (dir, mut db) = database_with(&[("a.md", "# A")]);
+        let b = dir.path().join("notes/b.md");
+        std::fs::write(&b, "# B").unwrap();
+        let z = Zest::from_file(b.to_str().unwrap().to_owned()).unwrap();
+        std::fs::remove_file(&b).unwrap();
+
+        assert_matches!(db.put(z.clone()), Err(DatabaseError::DirectoryError(..)));
+        let report = db.put_multiple(vec![z]).unwrap();
+        assert_eq!((report.added, report.failed.len()), (0, 1));
+        assert_eq!(db.list_all().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reindex_file_replaced_by_directory() {
+        let (dir, mut db) = database_with(&[("a.md", "# A"), ("b.md", "# B")]);
+        let a = dir.path().join("notes/a.md");
+        std::fs::remove_file(&a).unwrap();
+        std::fs::create_dir(&a).unwrap();
+
+        assert_eq!(db.reindex().unwrap().updated, 1);
+        assert_eq!(db.list_all().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn scan_with_limit() {
+        let (dir, mut db) = database_with(&[]);
+        let notes = dir.path().join("notes");
+        for name in &["a.md", "b.md", "c.md"] {
+            std::fs::write(notes.join(name), "# Note").unwrap();
+        }
+
+        let report = db.scan_with_limit(Some(2)).unwrap();
+        assert_eq!(report.added, 2);
+        assert!(report.truncated);
+        let report = db.scan_with_limit(Some(2)).unwrap();
+        assert_eq!(report.added, 1);
+        assert!(!report.truncated);
+        assert_eq!(db.list_all().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn cancel_scan() {
+        let (dir, mut db) = database_with(&[]);
+        std::fs::write(dir.path().join("notes/a.md"), "# Note").unwrap();
+
+        let cancel = Arc::new(AtomicBool::new(true));
+        db.set_cancel_flag(cancel.clone());
+        let report = db.scan().unwrap();
+        assert_eq!(report.added, 0);
+        assert!(report.truncated);
+
+        cancel.store(false, Ordering::Relaxed);
+        assert_eq!(db.scan().unwrap().added, 1);
+    }
+}
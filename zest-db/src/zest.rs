@@ -0,0 +1,1277 @@
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::path::Path;
+use tantivy::chrono::{DateTime as ChronoDateTime, NaiveDate, NaiveDateTime, Utc};
+use tantivy::DateTime;
+
+#[derive(Debug)]
+pub enum ZestParsingError {
+    SourceError(std::io::Error),
+    MetadataError(String),
+    FileTooLarge { path: String, size: u64 },
+    BinaryFile(String),
+}
+
+impl Display for ZestParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SourceError(e) => e.fmt(f),
+            Self::MetadataError(s) => write!(f, "Error while parsing metadata: {}", s),
+            Self::FileTooLarge { path, size } => {
+                write!(f, "{} is too large to be parsed ({} bytes)", path, size)
+            }
+            Self::BinaryFile(path) => write!(f, "{} is a binary file", path),
+        }
+    }
+}
+
+impl Error for ZestParsingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::SourceError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Reads any YAML scalar as a string, e.g. `date: 2024`, which would otherwise be a number
+fn scalar_string<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(
+        match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+            Some(serde_yaml::Value::String(s)) => Some(s),
+            Some(serde_yaml::Value::Number(n)) => Some(n.to_string()),
+            Some(serde_yaml::Value::Bool(b)) => Some(b.to_string()),
+            _ => None,
+        },
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct ZestMeta {
+    /// Title of the note, which takes precedence over its first heading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Creation date of the note, kept as written, see `Zest::created_at`
+    #[serde(
+        default,
+        deserialize_with = "scalar_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub date: Option<String>,
+    /// Other names of the note, searchable as its title
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// A human-written synopsis of the note
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Same as `description`, as used by some other tools
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Zest {
+    pub title: String,
+    /// Other titles of the note, that is its first heading when it differs from the title set in
+    /// its metadata
+    pub alt_titles: Vec<String>,
+    pub content: String,
+    pub file: String,
+    /// Links to other notes, without their anchors
+    pub refs: Vec<String>,
+    /// Links with a scheme, like `https://`, which point outside of the notes
+    pub external_links: Vec<String>,
+    pub metadata: ZestMeta,
+    pub created_at: Option<DateTime>,
+    pub sections: Vec<Section>,
+    /// Content of the code blocks of the note
+    pub code_blocks: Vec<String>,
+    /// Inline code spans of the note, like `this`
+    pub inline_code: Vec<String>,
+    /// Tags written in the text of the note, like `#todo`, see `use_inline_tags`
+    pub inline_tags: Vec<String>,
+    /// Ranges of `content` where the inline tags are written, leaving aside the ones of code spans
+    inline_tag_ranges: Vec<Range<usize>>,
+    /// Hash of the parsed title, content and tags of the note, set when it is read from a file, to
+    /// compare notes in memory. The database does not use it to decide what to reindex, see
+    /// `compute_content_hash`.
+    pub content_hash: Option<[u8; 32]>,
+    /// Number of indexed notes linking to this one, filled in when the note is returned by a
+    /// search of the database. It is zero for notes read from their file directly.
+    pub backlink_count: usize,
+}
+
+impl AsRef<Path> for Zest {
+    fn as_ref(&self) -> &Path {
+        self.path()
+    }
+}
+
+/// A section of a note, starting at a heading
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub heading: String,
+    pub level: u8,
+    /// Line of the heading in the file, starting at 1
+    pub start_line: usize,
+    /// Last line of the section, that is the line before the next heading of the same level or
+    /// above, or the last line of the file
+    pub end_line: usize,
+}
+
+impl Section {
+    fn new(level: u8, start_line: usize) -> Self {
+        Section {
+            heading: String::new(),
+            level,
+            start_line,
+            end_line: start_line,
+        }
+    }
+
+    /// Computes the end lines of `sections`, ordered by start line, in a file of `line_count`
+    /// lines
+    fn compute_ends(sections: &mut [Section], line_count: usize) {
+        for i in 0..sections.len() {
+            let next = sections[i + 1..]
+                .iter()
+                .find(|s| s.level <= sections[i].level)
+                .map(|s| s.start_line - 1);
+            sections[i].end_line = next.unwrap_or(line_count);
+        }
+    }
+}
+
+impl Zest {
+    fn new(
+        title: String,
+        content: String,
+        file: String,
+        refs: Vec<String>,
+        metadata: ZestMeta,
+        created_at: Option<DateTime>,
+        sections: Vec<Section>,
+    ) -> Self {
+        let (refs, external_links) = Self::split_refs(refs);
+        let (title, alt_titles) = match metadata.title {
+            Some(ref meta_title) if !title.is_empty() && *meta_title != title => {
+                (meta_title.clone(), vec![title])
+            }
+            Some(ref meta_title) => (meta_title.clone(), Vec::new()),
+            None => (title, Vec::new()),
+        };
+        Zest {
+            title,
+            alt_titles,
+            content,
+            file,
+            refs,
+            external_links,
+            metadata,
+            created_at,
+            sections,
+            code_blocks: Vec::new(),
+            inline_code: Vec::new(),
+            inline_tags: Vec::new(),
+            inline_tag_ranges: Vec::new(),
+            content_hash: None,
+            backlink_count: 0,
+        }
+    }
+
+    /// Sorts link destinations into links to other notes and external links. Anchors in the note
+    /// itself (`#heading`) are dropped, and so are the anchors of the links to other notes.
+    pub(crate) fn split_refs(dests: Vec<String>) -> (Vec<String>, Vec<String>) {
+        let mut refs = Vec::new();
+        let mut external = Vec::new();
+        for dest in dests {
+            if dest.starts_with('#') {
+                continue;
+            }
+
+            let has_scheme = dest.split_once(':').is_some_and(|(scheme, _)| {
+                scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+            });
+            if has_scheme {
+                external.push(dest);
+            } else {
+                match dest.split_once('#') {
+                    Some((path, _)) => refs.push(path.to_owned()),
+                    None => refs.push(dest),
+                }
+            }
+        }
+        (refs, external)
+    }
+
+    /// Parses the creation date of a note: a plain date (`2024-01-15`, `2024/01/15`) or a year, a
+    /// RFC3339 datetime, or a datetime as written by Jekyll (`2024-01-15 10:30:00 +0100`). Dates
+    /// without a timezone are taken as UTC.
+    fn parse_date(date: &str) -> Result<DateTime, ZestParsingError> {
+        const DATES: [&str; 2] = ["%Y-%m-%d", "%Y/%m/%d"];
+        const DATETIMES: [&str; 4] = [
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%d %H:%M",
+            "%Y-%m-%dT%H:%M:%S",
+            "%Y-%m-%dT%H:%M",
+        ];
+
+        let date = date.trim();
+        if let Ok(d) = ChronoDateTime::parse_from_rfc3339(date)
+            .or_else(|_| ChronoDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"))
+        {
+            return Ok(d.with_timezone(&Utc));
+        }
+        if let Some(d) = DATETIMES
+            .iter()
+            .find_map(|f| NaiveDateTime::parse_from_str(date, f).ok())
+        {
+            return Ok(DateTime::from_utc(d, Utc));
+        }
+        let day = DATES
+            .iter()
+            .find_map(|f| NaiveDate::parse_from_str(date, f).ok())
+            .or_else(|| {
+                date.parse::<i32>()
+                    .ok()
+                    .filter(|_| date.len() == 4)
+                    .and_then(|year| NaiveDate::from_ymd_opt(year, 1, 1))
+            });
+        match day {
+            Some(d) => Ok(DateTime::from_utc(d.and_hms(0, 0, 0), Utc)),
+            None => Err(ZestParsingError::MetadataError(format!(
+                "invalid date {}",
+                date
+            ))),
+        }
+    }
+
+    /// The creation date of a note with the metadata `metadata`. A date that can't be parsed does
+    /// not make the whole note unreadable, the note then has no creation date.
+    fn created_at(source: &str, metadata: &ZestMeta) -> Option<DateTime> {
+        match Self::parse_date(metadata.date.as_deref()?) {
+            Ok(d) => Some(d),
+            Err(e) => {
+                log::warn!("{}: {}, ignoring it", source, e);
+                None
+            }
+        }
+    }
+
+    /// The path of the file of this note
+    pub fn path(&self) -> &Path {
+        Path::new(&self.file)
+    }
+
+    /// The tags of this note, as set in its metadata
+    pub fn tags(&self) -> &[String] {
+        &self.metadata.tags
+    }
+
+    /// The synopsis of this note, as set in its metadata either as `description` or `summary`
+    pub fn description(&self) -> Option<&str> {
+        self.metadata
+            .description
+            .as_deref()
+            .or(self.metadata.summary.as_deref())
+    }
+
+    /// Replaces the tag `old` of this note by `new`, returning whether the note had it
+    pub fn rename_tag(&mut self, old: &str, new: &str) -> bool {
+        let tags = &mut self.metadata.tags;
+        let had_new = tags.iter().any(|t| t == new);
+        match tags.iter().position(|t| t == old) {
+            Some(i) if had_new => {
+                tags.remove(i);
+                true
+            }
+            Some(i) => {
+                tags[i] = new.to_owned();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Writes the metadata of this note to the yaml header of its file. The other keys of the
+    /// header, and the rest of the file, are kept as they are.
+    pub fn write_to_file(&self) -> Result<(), ZestParsingError> {
+        if FileFormat::from_path(&self.file) != FileFormat::Markdown {
+            return Err(ZestParsingError::MetadataError(format!(
+                "{} is not a markdown note",
+                self.file
+            )));
+        }
+
+        let raw = std::fs::read_to_string(self).map_err(|e| ZestParsingError::SourceError(e))?;
+        let (header, body) = split_header(&raw);
+
+        // The header without its delimiters
+        let lines: Vec<&str> = header.split_inclusive('\n').collect();
+        let fields = lines
+            .get(1..lines.len().saturating_sub(1))
+            .unwrap_or(&[])
+            .concat();
+        let mut fields: serde_yaml::Mapping = if fields.trim().is_empty() {
+            serde_yaml::Mapping::new()
+        } else {
+            serde_yaml::from_str(&fields)
+                .map_err(|e| ZestParsingError::MetadataError(e.to_string()))?
+        };
+        let metadata = serde_yaml::to_value(&self.metadata)
+            .map_err(|e| ZestParsingError::MetadataError(e.to_string()))?;
+        if let serde_yaml::Value::Mapping(metadata) = metadata {
+            for (key, value) in metadata {
+                fields.insert(key, value);
+            }
+        }
+
+        // The serialized metadata starts with the opening line of the header
+        let header = serde_yaml::to_string(&fields)
+            .map_err(|e| ZestParsingError::MetadataError(e.to_string()))?;
+        std::fs::write(self, format!("{}---\n{}", header, body))
+            .map_err(|e| ZestParsingError::SourceError(e))
+    }
+
+    /// Combines this note with `newer`, a newer version of it: the title, the content and the rest
+    /// of the metadata of `newer` are kept, but the tags and the links of both are.
+    pub fn merge(&self, newer: &Zest) -> Zest {
+        fn union(newer: &[String], older: &[String]) -> Vec<String> {
+            let mut merged = newer.to_vec();
+            for item in older {
+                if !merged.contains(item) {
+                    merged.push(item.clone());
+                }
+            }
+            merged
+        }
+
+        let mut merged = newer.clone();
+        merged.metadata.tags = union(&newer.metadata.tags, &self.metadata.tags);
+        merged.refs = union(&newer.refs, &self.refs);
+        if merged.content_hash.is_some() {
+            merged.content_hash = Some(merged.compute_content_hash());
+        }
+        merged
+    }
+
+    /// Checks this note for likely mistakes, and returns a human-readable warning for each of them:
+    /// a missing title, links to files that don't exist, duplicate tags and an invalid date. Links
+    /// are only looked up next to the note: the database also resolves them through the index,
+    /// e.g. by the file name of the note without its extension.
+    pub fn validate(&self) -> Vec<String> {
+        let mut warnings = self.validate_metadata();
+
+        let dir = self.path().parent().unwrap_or_else(|| Path::new(""));
+        for reff in &self.refs {
+            if !reff.is_empty() && !dir.join(reff).exists() {
+                warnings.push(format!(
+                    "{} links to {}, which does not exist",
+                    self.file, reff
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    /// Same as `validate`, without checking the links
+    pub(crate) fn validate_metadata(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.title.is_empty() {
+            warnings.push(format!("{} has no title", self.file));
+        }
+
+        let mut seen: Vec<&str> = Vec::with_capacity(self.metadata.tags.len());
+        for tag in &self.metadata.tags {
+            if seen.contains(&tag.as_str()) {
+                warnings.push(format!("{} has the tag {} more than once", self.file, tag));
+            } else {
+                seen.push(tag);
+            }
+        }
+
+        if let Some(Err(e)) = self.metadata.date.as_deref().map(Self::parse_date) {
+            warnings.push(format!("{}: {}", self.file, e));
+        }
+
+        warnings
+    }
+
+    /// Adds the tags written in the text of the note to its tags, and removes them from its
+    /// content so that they don't match full-text searches. The `#tags` of code spans are kept.
+    pub fn use_inline_tags(&mut self) {
+        if self.inline_tags.is_empty() {
+            return;
+        }
+
+        let mut content = String::with_capacity(self.content.len());
+        let mut last = 0;
+        for range in self.inline_tag_ranges.drain(..) {
+            content.push_str(&self.content[last..range.start]);
+            last = range.end;
+        }
+        content.push_str(&self.content[last..]);
+        self.content = content;
+
+        for tag in &self.inline_tags {
+            if !self.metadata.tags.contains(tag) {
+                self.metadata.tags.push(tag.clone());
+            }
+        }
+    }
+
+    /// The number of links of this note to other notes
+    pub fn count_links(&self) -> usize {
+        self.refs.len()
+    }
+
+    /// The number of links to other notes per 100 words of content
+    pub fn link_density(&self) -> f64 {
+        let words = self.content.split_whitespace().count();
+        if words == 0 {
+            0.0
+        } else {
+            self.count_links() as f64 * 100.0 / words as f64
+        }
+    }
+
+    pub fn from_file(source: String) -> Result<Self, ZestParsingError> {
+        Self::from_file_with_limit(source, None)
+    }
+
+    /// Parses the note at `source`, failing without reading it if it is bigger than `max_size`
+    /// bytes
+    pub fn from_file_with_limit(
+        source: String,
+        max_size: Option<u64>,
+    ) -> Result<Self, ZestParsingError> {
+        let file = File::open(&source).map_err(|e| ZestParsingError::SourceError(e))?;
+        if let Some(max_size) = max_size {
+            let size = file
+                .metadata()
+                .map_err(|e| ZestParsingError::SourceError(e))?
+                .len();
+            if size > max_size {
+                return Err(ZestParsingError::FileTooLarge { path: source, size });
+            }
+        }
+        let mut reader = BufReader::new(file);
+
+        // Text files never contain null bytes, unlike most binary files
+        const BINARY_CHECK_LEN: usize = 512;
+        let head = reader
+            .fill_buf()
+            .map_err(|e| ZestParsingError::SourceError(e))?;
+        if head.iter().take(BINARY_CHECK_LEN).any(|b| *b == 0) {
+            return Err(ZestParsingError::BinaryFile(source));
+        }
+
+        let mut z = match FileFormat::from_path(&source) {
+            FileFormat::Markdown => Self::from_markdown(source, reader),
+            FileFormat::Org => Self::from_org(source, reader),
+        }?;
+        z.content_hash = Some(z.compute_content_hash());
+        Ok(z)
+    }
+
+    /// Computes the SHA256 of the parsed title, content and tags of this note. Unlike the hash of
+    /// the file, it does not change with the formatting of the note, like extra blank lines. It
+    /// does not cover the links or the sections either, which is why `Database::update` compares
+    /// the hash of the file instead.
+    pub fn compute_content_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for part in [&self.title, &self.content]
+            .iter()
+            .copied()
+            .chain(self.tags())
+        {
+            hasher.update(part.as_bytes());
+            // Separate the parts, so that moving text from one to the other changes the hash
+            hasher.update([0]);
+        }
+        hasher.finalize().into()
+    }
+
+    /// Recursively walks `dir` and yields the paths of the notes it contains. Hidden files and
+    /// directories are skipped, and so are the files that do not look like notes according to
+    /// their extension. Symbolic links are only followed when `follow_symlinks` is set, and the
+    /// walk never leaves the filesystem of `dir`.
+    pub fn walk_directory(dir: &Path, follow_symlinks: bool) -> impl Iterator<Item = String> {
+        walkdir::WalkDir::new(dir)
+            .follow_links(follow_symlinks)
+            .same_file_system(true)
+            .into_iter()
+            .filter_entry(|e| {
+                log::trace!("Considering {}", e.path().display());
+                e.depth() == 0
+                    || e.file_name()
+                        .to_str()
+                        .map(|s| !s.starts_with('.'))
+                        .unwrap_or(false)
+            })
+            .filter_map(|e| match e {
+                Ok(e) => Some(e),
+                Err(e) => {
+                    // Notably symbolic link loops
+                    log::warn!("Skipping a directory entry: {}", e);
+                    None
+                }
+            })
+            .filter(|e| e.file_type().is_file() && FileFormat::from_extension(e.path()).is_some())
+            .filter_map(|e| e.path().to_str().map(String::from))
+    }
+
+    /// Lazily parses all the notes contained in `dir`, see `walk_directory` and
+    /// `from_file_with_limit`
+    pub fn from_directory(
+        dir: &Path,
+        max_size: Option<u64>,
+        follow_symlinks: bool,
+    ) -> impl Iterator<Item = Result<Self, ZestParsingError>> {
+        Self::walk_directory(dir, follow_symlinks)
+            .map(move |f| Self::from_file_with_limit(f, max_size))
+    }
+
+    /// Converts this markdown note to an Obsidian note: its metadata is kept as is, and the links
+    /// for which `resolve` gives the name of the target note are replaced by wikilinks.
+    pub fn to_obsidian<F>(&self, resolve: F) -> std::io::Result<String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let raw = std::fs::read_to_string(self)?;
+        let (header, body) = split_header(&raw);
+
+        let mut out = String::with_capacity(raw.len());
+        out.push_str(header);
+
+        // The link being rewritten: its range in the body, its target and its text
+        let mut link: Option<(Range<usize>, String, String)> = None;
+        let mut last = 0;
+        for (evt, range) in Parser::new(body).into_offset_iter() {
+            match evt {
+                Event::Start(Tag::Link(_, dest, _)) => {
+                    link = resolve(dest.as_ref()).map(|target| (range, target, String::new()))
+                }
+                Event::Text(t) | Event::Code(t) => {
+                    if let Some((_, _, ref mut text)) = link {
+                        text.push_str(t.as_ref());
+                    }
+                }
+                Event::End(Tag::Link(..)) => {
+                    if let Some((range, target, text)) = link.take() {
+                        out.push_str(&body[last..range.start]);
+                        if text.is_empty() || text == target {
+                            out.push_str(&format!("[[{}]]", target));
+                        } else {
+                            out.push_str(&format!("[[{}|{}]]", target, text));
+                        }
+                        last = range.end;
+                    }
+                }
+                _ => {}
+            }
+        }
+        out.push_str(&body[last..]);
+
+        Ok(out)
+    }
+
+    fn from_markdown<R: BufRead>(source: String, reader: R) -> Result<Self, ZestParsingError> {
+        // TODO(vigoux): not really optimal because there's a lot of allocations, but that should
+        // not happen very often...
+
+        // Split the file in two parts: the metadata part (in a yaml header, if any) and the
+        // markdown lines.
+
+        let mut metadata = String::new();
+        let mut md_lines = String::new();
+
+        // Number of lines before the markdown ones, to report sections positions in the file
+        let mut header_lines = 0;
+        let mut line_count = 0;
+
+        let mut in_header = false;
+        // The lines of the header, in case it is never closed
+        let mut header_md = String::new();
+        for (i, line) in reader.lines().filter_map(|l| l.ok()).enumerate() {
+            line_count = i + 1;
+            // Some editors start the files with a byte order mark, which would hide the header
+            let line = if i == 0 {
+                line.trim_start_matches('\u{feff}')
+            } else {
+                &line
+            };
+            match (i, line, in_header) {
+                (0, "---", false) => {
+                    in_header = true;
+                    header_md.push_str(line);
+                    header_md.push('\n');
+                }
+                (_, "---", true) => {
+                    in_header = false;
+                    header_lines = i + 1;
+                }
+                (_, l, true) => {
+                    if !metadata.is_empty() {
+                        metadata.push('\n');
+                    }
+                    metadata.push_str(l);
+                    header_md.push_str(l);
+                    header_md.push('\n');
+                }
+                (_, l, false) => {
+                    // Always terminate lines, so that leading blank lines are kept and line
+                    // numbers can be computed from offsets
+                    md_lines.push_str(l);
+                    md_lines.push('\n');
+                }
+            }
+        }
+
+        if in_header {
+            // The header is never closed, so its first line was a thematic break, not the start of
+            // a header: the whole file is markdown
+            md_lines = header_md;
+            metadata.clear();
+        }
+
+        let mut title = String::new();
+        let mut content = String::new();
+        let mut refs = Vec::new();
+        let mut sections = Vec::new();
+        let mut heading: Option<Section> = None;
+        let mut code_blocks: Vec<String> = Vec::new();
+        let mut inline_code = Vec::new();
+        let mut inline_tags = Vec::new();
+        let mut inline_tag_ranges = Vec::new();
+        let mut in_code_block = false;
+
+        // Now that we've split it, parse the markdown first
+        // to extract the text's content
+        let mut in_title = false;
+        for (evt, range) in Parser::new(md_lines.as_ref()).into_offset_iter() {
+            match &evt {
+                Event::Start(Tag::Heading(level)) => {
+                    let line = header_lines + md_lines[..range.start].matches('\n').count() + 1;
+                    heading = Some(Section::new(*level as u8, line));
+                }
+                Event::Text(t) if in_code_block => {
+                    if let Some(block) = code_blocks.last_mut() {
+                        block.push_str(t.as_ref());
+                    }
+                }
+                Event::Text(t) | Event::Code(t) => {
+                    if let Some(ref mut h) = heading {
+                        h.heading.push_str(t.as_ref());
+                    }
+                }
+                Event::End(Tag::Heading(_)) => sections.extend(heading.take()),
+                Event::Start(Tag::CodeBlock(_)) => {
+                    in_code_block = true;
+                    code_blocks.push(String::new());
+                }
+                Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+                _ => {}
+            }
+
+            match (in_title, evt) {
+                // title handling
+                (false, Event::Start(Tag::Heading(1))) if title.is_empty() => in_title = true,
+                // Emphasis only wraps the text of the title, which is kept, as the one of code
+                // spans and links
+                (true, Event::Text(t) | Event::Code(t)) => title.push_str(t.as_ref()),
+                (true, Event::End(Tag::Heading(1))) => in_title = false,
+
+                // Normal text handling
+                (false, Event::Text(t)) => {
+                    if !in_code_block {
+                        for (range, tag) in find_hashtags(&t) {
+                            inline_tags.push(tag.to_owned());
+                            inline_tag_ranges
+                                .push(content.len() + range.start..content.len() + range.end);
+                        }
+                    }
+                    content.push_str(t.as_ref());
+                }
+                (false, Event::Code(t)) => {
+                    content.push_str(t.as_ref());
+                    inline_code.push(t.into_string());
+                }
+
+                // TODO(vigoux): For now we ignore the type of the link, maybe at some point we
+                // will filter that and have different behaviors for this
+                (false, Event::Start(Tag::Link(_, dest, text))) => {
+                    content.push_str(text.as_ref());
+                    refs.push(String::from(dest.as_ref()));
+                }
+                (true, Event::Start(Tag::Link(_, dest, text))) => {
+                    title.push_str(text.as_ref());
+                    refs.push(String::from(dest.as_ref()));
+                }
+
+                // Newline handling, list items are not separated by any text event
+                (
+                    false,
+                    Event::SoftBreak
+                    | Event::HardBreak
+                    | Event::End(Tag::Heading(_))
+                    | Event::End(Tag::Item),
+                ) => content.push('\n'),
+
+                _ => {}
+            }
+        }
+
+        let metadata: ZestMeta = if !metadata.is_empty() {
+            serde_yaml::from_str(metadata.as_ref())
+                .map_err(|e| ZestParsingError::MetadataError(e.to_string()))?
+        } else {
+            ZestMeta::default()
+        };
+
+        let created_at = Self::created_at(&source, &metadata);
+
+        Section::compute_ends(&mut sections, line_count);
+
+        let mut z = Zest::new(title, content, source, refs, metadata, created_at, sections);
+        z.code_blocks = code_blocks;
+        z.inline_code = inline_code;
+        z.inline_tags = inline_tags;
+        z.inline_tag_ranges = inline_tag_ranges;
+        Ok(z)
+    }
+
+    fn from_org<R: BufRead>(source: String, reader: R) -> Result<Self, ZestParsingError> {
+        let mut title = String::new();
+        let mut content = String::new();
+        let mut refs = Vec::new();
+        let mut metadata = ZestMeta::default();
+        let mut sections = Vec::new();
+        let mut line_count = 0;
+
+        for (i, line) in reader.lines().map_while(Result::ok).enumerate() {
+            line_count = i + 1;
+
+            // In-buffer settings, like #+TITLE: My title
+            if let Some(setting) = line.strip_prefix("#+") {
+                if let Some((key, value)) = setting.split_once(':') {
+                    let value = value.trim();
+                    match key.to_uppercase().as_ref() {
+                        "TITLE" => title = Self::org_text(value, &mut refs),
+                        "TAGS" | "FILETAGS" => metadata.tags.extend(
+                            value
+                                .split(|c: char| c == ':' || c.is_whitespace())
+                                .filter(|t| !t.is_empty())
+                                .map(String::from),
+                        ),
+                        // Org timestamps look like <2024-01-15 Mon>
+                        "DATE" => {
+                            metadata.date = value
+                                .trim_matches(|c| c == '<' || c == '>' || c == '[' || c == ']')
+                                .split_whitespace()
+                                .next()
+                                .map(String::from)
+                        }
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+
+            // Comments
+            if line == "#" || line.starts_with("# ") {
+                continue;
+            }
+
+            let stars = line.chars().take_while(|c| *c == '*').count();
+            if stars > 0 && line[stars..].starts_with(' ') {
+                let heading = Self::org_text(line[stars..].trim(), &mut refs);
+                let mut section = Section::new(stars.min(u8::MAX as usize) as u8, i + 1);
+                section.heading = heading.clone();
+                sections.push(section);
+
+                if stars == 1 && title.is_empty() {
+                    title = heading;
+                } else {
+                    content.push_str(&heading);
+                    content.push('\n');
+                }
+                continue;
+            }
+
+            content.push_str(&Self::org_text(&line, &mut refs));
+            content.push('\n');
+        }
+
+        let created_at = Self::created_at(&source, &metadata);
+
+        Section::compute_ends(&mut sections, line_count);
+
+        Ok(Zest::new(
+            title, content, source, refs, metadata, created_at, sections,
+        ))
+    }
+
+    /// Replaces the org links (`[[dest][description]]` or `[[dest]]`) of `line` by their text, and
+    /// pushes their destinations in `refs`
+    fn org_text(line: &str, refs: &mut Vec<String>) -> String {
+        let mut text = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(start) = rest.find("[[") {
+            let end = match rest[start..].find("]]") {
+                Some(end) => start + end,
+                None => break,
+            };
+
+            text.push_str(&rest[..start]);
+            let link = &rest[start + 2..end];
+            let (dest, description) = link.split_once("][").unwrap_or((link, link));
+            text.push_str(description);
+            refs.push(String::from(dest.strip_prefix("file:").unwrap_or(dest)));
+
+            rest = &rest[end + 2..];
+        }
+        text.push_str(rest);
+        text
+    }
+}
+
+/// Finds the `#tags` of `text`, which start with a letter and follow a whitespace or the start of
+/// the text, and returns their ranges along with their names
+fn find_hashtags(text: &str) -> impl Iterator<Item = (Range<usize>, &str)> {
+    text.match_indices('#').filter_map(move |(start, _)| {
+        let preceded_by_space = text[..start]
+            .chars()
+            .next_back()
+            .is_none_or(char::is_whitespace);
+        let name = &text[start + 1..];
+        if !preceded_by_space || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        let len = name
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(name.len());
+        Some((start..start + 1 + len, &name[..len]))
+    })
+}
+
+/// Splits a markdown note between its yaml header, delimiters included, and its content
+fn split_header(raw: &str) -> (&str, &str) {
+    // Like when parsing the note, a leading BOM does not prevent the header from being found
+    let raw = raw.strip_prefix('\u{feff}').unwrap_or(raw);
+    let mut lines = raw.split_inclusive('\n');
+    let mut offset = match lines.next() {
+        Some(first) if first.trim_end() == "---" => first.len(),
+        _ => return ("", raw),
+    };
+
+    for line in lines {
+        offset += line.len();
+        if line.trim_end() == "---" {
+            return raw.split_at(offset);
+        }
+    }
+
+    ("", raw)
+}
+
+/// The markup languages notes can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Markdown,
+    Org,
+}
+
+impl FileFormat {
+    /// Guesses the format of a note from its extension, defaulting to markdown
+    pub fn from_path(path: &str) -> Self {
+        Self::from_extension(Path::new(path)).unwrap_or(Self::Markdown)
+    }
+
+    /// Guesses the format of a note from its extension, if it is a known one
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("md") | Some("markdown") => Some(Self::Markdown),
+            Some("org") => Some(Self::Org),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn parse(raw: &str) -> Result<Zest, ZestParsingError> {
+        Zest::from_markdown(String::from("note.md"), raw.as_bytes())
+    }
+
+    #[test]
+    fn empty_file() {
+        let file = NamedTempFile::new().unwrap();
+        let z = Zest::from_file(file.path().to_str().unwrap().to_owned()).unwrap();
+        assert_eq!(z.title, "");
+        assert_eq!(z.content, "");
+        assert!(z.refs.is_empty());
+        assert!(z.tags().is_empty());
+        assert!(z.created_at.is_none());
+    }
+
+    #[test]
+    fn title_only() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"# Only a title\n").unwrap();
+        let z = Zest::from_file(file.path().to_str().unwrap().to_owned()).unwrap();
+        assert_eq!(z.title, "Only a title");
+        assert_eq!(z.content, "");
+        assert_eq!(z.sections.len(), 1);
+        assert_eq!(z.sections[0].heading, "Only a title");
+    }
+
+    #[test]
+    fn frontmatter() {
+        let z = parse("---\ntags:\n  - foo\n  - bar\ndate: 2024-01-15\n---\n\n# Title\n\nText\n")
+            .unwrap();
+        assert_eq!(z.title, "Title");
+        assert_eq!(z.content, "Text");
+        assert_eq!(z.tags(), ["foo", "bar"]);
+        assert_eq!(
+            z.created_at.unwrap().to_rfc3339(),
+            "2024-01-15T00:00:00+00:00"
+        );
+        assert_eq!(z.sections[0].start_line, 8);
+    }
+
+    #[test]
+    fn aliases() {
+        let z = parse("---\naliases: [Other name, Another one]\n---\n# Title\n").unwrap();
+        assert_eq!(z.metadata.aliases, ["Other name", "Another one"]);
+        assert!(parse("# Title\n").unwrap().metadata.aliases.is_empty());
+    }
+
+    #[test]
+    fn metadata_title() {
+        let z = parse("---\ntitle: Real title\n---\n# Heading\n").unwrap();
+        assert_eq!(z.title, "Real title");
+        assert_eq!(z.alt_titles, ["Heading"]);
+
+        let z = parse("---\ntitle: Same\n---\n# Same\n").unwrap();
+        assert_eq!(z.title, "Same");
+        assert!(z.alt_titles.is_empty());
+
+        let z = parse("---\ntitle: Only\n---\nNo heading\n").unwrap();
+        assert_eq!(z.title, "Only");
+        assert!(z.alt_titles.is_empty());
+    }
+
+    #[test]
+    fn description() {
+        let z = parse("---\ndescription: About this\n---\n# Title\n").unwrap();
+        assert_eq!(z.description(), Some("About this"));
+        let z = parse("---\nsummary: About that\n---\n# Title\n").unwrap();
+        assert_eq!(z.description(), Some("About that"));
+        let z = parse("---\ndescription: This\nsummary: That\n---\n# Title\n").unwrap();
+        assert_eq!(z.description(), Some("This"));
+        assert_eq!(parse("# Title\n").unwrap().description(), None);
+    }
+
+    #[test]
+    fn nested_links() {
+        let z =
+            parse("# Title with [a link](a.md)\n\n- item [b](b.md)\n  - nested [c](sub/c.md)\n")
+                .unwrap();
+        assert_eq!(z.title, "Title with a link");
+        assert_eq!(z.refs, ["a.md", "b.md", "sub/c.md"]);
+        assert!(z.content.contains('b'));
+        assert!(z.content.contains('c'));
+    }
+
+    #[test]
+    fn link_kinds() {
+        let z = parse(
+            "# Links\n\n[anchor](#installation) [note](./a.md) [section](b.md#usage) \
+             [web](https://example.com) [mail](mailto:me@example.com)\n",
+        )
+        .unwrap();
+        assert_eq!(z.refs, ["./a.md", "b.md"]);
+        assert_eq!(
+            z.external_links,
+            ["https://example.com", "mailto:me@example.com"]
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let old = parse("---\ntags: [a, b]\n---\n# Old\n\nOld [x](x.md) [y](y.md)\n").unwrap();
+        let new = parse("---\ntags: [b, c]\n---\n# New\n\nNew [y](y.md) [z](z.md)\n").unwrap();
+
+        let merged = old.merge(&new);
+        assert_eq!(merged.title, "New");
+        assert_eq!(merged.content, "New y z");
+        assert_eq!(merged.tags(), ["b", "c", "a"]);
+        assert_eq!(merged.refs, ["y.md", "z.md", "x.md"]);
+    }
+
+    #[test]
+    fn validate() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("other.md"), "# Other").unwrap();
+        let file = dir.path().join("note.md").to_str().unwrap().to_owned();
+
+        let raw = "---\ntags: [a, b]\n---\n# Note\n\n[Other](other.md#part) and [web](https://example.com)";
+        let z = Zest::from_markdown(file.clone(), raw.as_bytes()).unwrap();
+        assert!(z.validate().is_empty());
+
+        let raw = "---\ntags: [a, b, a]\ndate: yesterday\n---\nNo title, [missing](missing.md)";
+        let z = Zest::from_markdown(file.clone(), raw.as_bytes()).unwrap();
+        let warnings = z.validate();
+        assert_eq!(warnings.len(), 4);
+        assert_eq!(warnings[0], format!("{} has no title", file));
+        assert!(warnings[1].contains("the tag a more than once"));
+        assert!(warnings[2].contains("invalid date yesterday"));
+        assert!(warnings[3].contains("missing.md"));
+        assert_eq!(z.validate_metadata(), warnings[..3]);
+    }
+
+    #[test]
+    fn inline_tags() {
+        let raw =
+            "---\ntags: [rust]\n---\n# Tags\n\nAbout #rust and #todo-later, not a#b or `#rust`\n";
+        let mut z = parse(raw).unwrap();
+        assert_eq!(z.inline_tags, ["rust", "todo-later"]);
+        assert_eq!(z.tags(), ["rust"]);
+
+        z.use_inline_tags();
+        assert_eq!(z.tags(), ["rust", "todo-later"]);
+        assert_eq!(z.content, "About  and , not a#b or #rust");
+    }
+
+    #[test]
+    fn formatted_title() {
+        let z = parse("# **Bold** *italic* `code` [link](url)\n\nContent").unwrap();
+        assert_eq!(z.title, "Bold italic code link");
+        assert_eq!(z.refs, ["url"]);
+        assert_eq!(z.content, "Content");
+    }
+
+    #[test]
+    fn list_items() {
+        let z = parse("# Languages\n\n- Python\n- Rust\n* Go\n\n1. First\n2. Second\n").unwrap();
+        let words: Vec<&str> = z.content.split_whitespace().collect();
+        assert_eq!(words, ["Python", "Rust", "Go", "First", "Second"]);
+    }
+
+    #[test]
+    fn unclosed_header() {
+        let z = parse("---\n# Title\n\nSome text: not yaml\n\n## Part\n").unwrap();
+        assert_eq!(z.title, "Title");
+        assert!(z.content.contains("Some text: not yaml"));
+        assert!(z.tags().is_empty());
+        assert_eq!(z.sections[1].heading, "Part");
+        assert_eq!(z.sections[1].start_line, 6);
+
+        assert_eq!(parse("---\n").unwrap().title, "");
+    }
+
+    #[test]
+    fn link_density() {
+        let z = parse("# Links\n\nSee [a](a.md) and [b](b.md) too\n").unwrap();
+        assert_eq!(z.count_links(), 2);
+        assert_eq!(z.link_density(), 40.0);
+        assert_eq!(parse("# Empty\n").unwrap().link_density(), 0.0);
+    }
+
+    #[test]
+    fn code_blocks() {
+        let z = parse("# Code\n\n```rust\nfn fenced() {}\n```\n\n    fn indented() {}\n").unwrap();
+        assert_eq!(z.title, "Code");
+        assert!(z.content.contains("fn fenced() {}"));
+        assert!(z.content.contains("fn indented() {}"));
+        assert!(z.refs.is_empty());
+        assert_eq!(z.code_blocks, ["fn fenced() {}\n", "fn indented() {}\n"]);
+    }
+
+    #[test]
+    fn inline_code() {
+        let z = parse("# Code\n\nUse a `HashMap` or a `BTreeMap` here.\n").unwrap();
+        assert_eq!(z.inline_code, ["HashMap", "BTreeMap"]);
+        assert_eq!(z.content, "Use a HashMap or a BTreeMap here.");
+        assert!(z.code_blocks.is_empty());
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let z = parse("---\r\ntags:\r\n  - foo\r\n---\r\n# Title\r\n\r\nText\r\n").unwrap();
+        assert_eq!(z.title, "Title");
+        assert_eq!(z.content, "Text");
+        assert_eq!(z.tags(), ["foo"]);
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let z = parse("\u{feff}---\ntags:\n  - foo\n---\n# Title\n").unwrap();
+        assert_eq!(z.title, "Title");
+        assert_eq!(z.tags(), ["foo"]);
+    }
+
+    #[test]
+    fn binary_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"%PDF-1.4\n\x00\x01\x02binary").unwrap();
+        let path = file.path().to_str().unwrap().to_owned();
+        assert!(matches!(
+            Zest::from_file(path.clone()),
+            Err(ZestParsingError::BinaryFile(p)) if p == path
+        ));
+    }
+
+    #[test]
+    fn write_to_file_with_bom() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("\u{feff}---\ntags: [foo]\n---\n# Title\n\nText\n".as_bytes())
+            .unwrap();
+        let path = file.path().to_str().unwrap().to_owned();
+
+        let mut z = Zest::from_file(path.clone()).unwrap();
+        assert!(z.rename_tag("foo", "bar"));
+        z.write_to_file().unwrap();
+
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(raw.matches("---\n").count(), 2);
+        let z = Zest::from_file(path).unwrap();
+        assert_eq!(z.tags(), ["bar"]);
+        assert_eq!(z.title, "Title");
+        assert_eq!(z.content, "Text");
+    }
+
+    #[test]
+    fn content_hash() {
+        let from_file = |raw: &str| {
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(raw.as_bytes()).unwrap();
+            Zest::from_file(file.path().to_str().unwrap().to_owned())
+                .unwrap()
+                .content_hash
+                .unwrap()
+        };
+
+        let hash = from_file("---\ntags: [foo]\n---\n# Title\n\nText\n");
+        assert_eq!(
+            hash,
+            from_file("---\ntags: [foo]\n---\n\n\n# Title\n\n\n\nText\n\n")
+        );
+        assert_ne!(hash, from_file("---\ntags: [bar]\n---\n# Title\n\nText\n"));
+        assert_ne!(hash, from_file("---\ntags: [foo]\n---\n# Title\n\nOther\n"));
+        assert!(parse("# Title\n").unwrap().content_hash.is_none());
+    }
+
+    #[test]
+    fn malformed_frontmatter() {
+        assert!(matches!(
+            parse("---\ntags: [foo\n---\n# Title\n"),
+            Err(ZestParsingError::MetadataError(_))
+        ));
+
+        // An invalid date does not prevent the note from being read
+        let z = parse("---\ndate: yesterday\n---\n# Title\n").unwrap();
+        assert_eq!(z.title, "Title");
+        assert_eq!(z.metadata.date.as_deref(), Some("yesterday"));
+        assert!(z.created_at.is_none());
+        assert!(parse("---\ndate: January 5\n---\n# Title\n")
+            .unwrap()
+            .created_at
+            .is_none());
+    }
+
+    #[test]
+    fn date_formats() {
+        let created_at = |date: &str| {
+            parse(&format!("---\ndate: {}\n---\n# Title\n", date))
+                .unwrap()
+                .created_at
+                .map(|d| d.to_rfc3339())
+        };
+        let day = Some(String::from("2024-01-15T00:00:00+00:00"));
+        assert_eq!(created_at("2024-01-15"), day);
+        assert_eq!(created_at("2024/01/15"), day);
+        assert_eq!(created_at("2024-01-15T00:00:00Z"), day);
+        assert_eq!(
+            created_at("2024-01-15 10:30:00 +0100"),
+            Some(String::from("2024-01-15T09:30:00+00:00"))
+        );
+        assert_eq!(
+            created_at("2024-01-15 10:30"),
+            Some(String::from("2024-01-15T10:30:00+00:00"))
+        );
+        assert_eq!(
+            created_at("2024"),
+            Some(String::from("2024-01-01T00:00:00+00:00"))
+        );
+    }
+
+    /// Writes a markdown note, separating the paragraphs with blank lines
+    fn render(title: &str, tags: &[String], paragraphs: &[String]) -> String {
+        let mut out = String::from("---\ntags:");
+        if tags.is_empty() {
+            out.push_str(" []");
+        }
+        out.push('\n');
+        for tag in tags {
+            out.push_str(&format!("  - \"{}\"\n", tag));
+        }
+        out.push_str(&format!("---\n\n# {}\n", title));
+        for p in paragraphs {
+            out.push_str(&format!("\n{}\n", p));
+        }
+        out
+    }
+
+    fn without_whitespace(s: &str) -> String {
+        s.split_whitespace().collect()
+    }
+
+    proptest! {
+        #[test]
+        fn markdown_round_trip(
+            title in "[A-Za-z0-9][A-Za-z0-9 ]{0,30}",
+            tags in prop::collection::vec("[a-z0-9]{1,10}", 0..5),
+            paragraphs in prop::collection::vec(
+                prop_oneof![Just(String::from("---")), "[A-Za-z][A-Za-z0-9 .,]{0,40}"],
+                0..5,
+            ),
+        ) {
+            let z = parse(&render(&title, &tags, &paragraphs)).unwrap();
+
+            prop_assert_eq!(z.title.as_str(), title.trim());
+            prop_assert_eq!(z.tags(), &tags[..]);
+
+            // A `---` surrounded by blank lines is a thematic break, not a header delimiter
+            let text: Vec<&str> = paragraphs
+                .iter()
+                .map(String::as_str)
+                .filter(|p| *p != "---")
+                .collect();
+            prop_assert_eq!(without_whitespace(&z.content), without_whitespace(&text.concat()));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Runs the add, search and remove cycle against the XDG directories. The XDG environment
+//! variables are set for the whole process, and the directories are only looked up once, so this
+//! test lives in its own binary.
+
+use assert_matches::assert_matches;
+use tempfile::TempDir;
+use zest_db::{Database, PutReport, Zest};
+
+#[test]
+fn add_search_remove() {
+    let dir = TempDir::new().unwrap();
+    std::env::set_var("XDG_CACHE_HOME", dir.path().join("cache"));
+    std::env::set_var("XDG_CONFIG_HOME", dir.path().join("config"));
+
+    let notes_dir = dir.path().join("notes");
+    std::fs::create_dir(&notes_dir).unwrap();
+    std::fs::write(
+        notes_dir.join("first.md"),
+        "---\ntags:\n  - foo\n---\n\n# First\n\nSee [second](second.md)\n",
+    )
+    .unwrap();
+    std::fs::write(
+        notes_dir.join("second.md"),
+        "# Second\n\n```rust\nfn main() {}\n```\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.path().join("config/zest")).unwrap();
+    std::fs::write(
+        dir.path().join("config/zest/config.yml"),
+        format!("paths:\n  - {}\n", notes_dir.display()),
+    )
+    .unwrap();
+
+    let mut db = Database::open().unwrap();
+    assert!(dir.path().join("cache/zest/index").is_dir());
+    assert_eq!(
+        Database::config_file(None).unwrap(),
+        dir.path().join("config/zest/config.yml")
+    );
+
+    let notes: Vec<Zest> = Zest::from_directory(&notes_dir, None, false)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(notes.len(), 2);
+    assert_matches!(
+        db.put_multiple(notes.clone()),
+        Ok(PutReport { added: 2, updated: 0, ref failed, .. }) if failed.is_empty()
+    );
+
+    assert_eq!(db.list_all().unwrap().len(), 2);
+    assert_eq!(db.list(String::from("tag:foo")).unwrap().len(), 1);
+    assert_eq!(db.list(String::from("main")).unwrap().len(), 1);
+
+    // Adding the notes again replaces them
+    assert_matches!(
+        db.put_multiple(notes),
+        Ok(PutReport {
+            added: 0,
+            updated: 2,
+            ..
+        })
+    );
+    let found = db.search(String::from("*")).unwrap();
+    let titles: Vec<&str> = found.iter().map(|z| z.title.as_str()).collect();
+    assert_eq!(titles, ["First", "Second"]);
+
+    db.remove(String::from("title:first")).unwrap();
+    let found = db.search(String::from("*")).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].title, "Second");
+}
@@ -0,0 +1,35 @@
+//! Checks that `~` and the environment variables are expanded in the configured paths. The
+//! variables are set for the whole process, so this test lives in its own binary.
+
+use assert_matches::assert_matches;
+use tempfile::TempDir;
+use zest_db::{Database, DatabaseError};
+
+#[test]
+fn expand_config_paths() {
+    let dir = TempDir::new().unwrap();
+    std::env::set_var("HOME", dir.path().join("home"));
+    std::env::set_var("ZEST_TEST_NOTES", dir.path().join("srv"));
+    for notes in &["home/notes", "srv/work"] {
+        let notes = dir.path().join(notes);
+        std::fs::create_dir_all(&notes).unwrap();
+        std::fs::write(notes.join("note.md"), "# Note").unwrap();
+    }
+
+    let config = dir.path().join("config.yml");
+    let index = dir.path().join("index");
+    std::fs::write(
+        &config,
+        "paths:\n  - ~/notes\n  - $ZEST_TEST_NOTES/work\n  - /plain\n",
+    )
+    .unwrap();
+    let mut db = Database::open_or_create_at(Some(&index), Some(&config), false).unwrap();
+    assert_eq!(db.scan().unwrap().added, 2);
+    drop(db);
+
+    std::fs::write(&config, "paths:\n  - $ZEST_TEST_UNDEFINED/notes\n").unwrap();
+    assert_matches!(
+        Database::open_or_create_at(Some(&index), Some(&config), false).err(),
+        Some(DatabaseError::ConfigError(_))
+    );
+}